@@ -0,0 +1,3068 @@
+extern crate libc;
+#[macro_use]
+extern crate serde_json;
+
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::{self, Read, Write};
+#[cfg(target_os = "linux")]
+use std::mem;
+use std::panic;
+#[cfg(target_os = "linux")]
+use std::process;
+use std::str::{self, FromStr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::thread::{self, JoinHandle};
+
+// The default number of space characters (" ") between table columns.
+const COLUMN_BUFFER: usize = 8;
+
+// The default number of milliseconds to sleep between calls to `run_ps`.
+pub const DEFAULT_PS_INTERVAL_MS: u64 = 100;
+
+// Short lived child processes (e.g. small test binaries) may exit before a
+// `run_ps` call finds them, so for the first `DEFAULT_FAST_PS_WINDOW_MS`
+// milliseconds of monitoring we only sleep `FAST_PS_INTERVAL_MS` milliseconds
+// between calls.
+pub const FAST_PS_INTERVAL_MS: u64 = 5;
+pub const DEFAULT_FAST_PS_WINDOW_MS: u64 = 1000;
+
+// The default number of milliseconds to sleep between reads of the child
+// processes' "status" files.
+pub const DEFAULT_MEASUREMENT_INTERVAL_MS: u64 = 1;
+
+// By default, warn when a process locks at least this percentage of a locked
+// memory limit.
+pub const LIMIT_WARN_PERCENT: u64 = 90;
+
+// The "status" file field measured by default.
+pub const DEFAULT_FIELD: &str = "VmLck";
+
+// Where the proc filesystem is usually mounted.
+pub const DEFAULT_PROCFS: &str = "/proc";
+
+// How often the worker threads search for new child processes and measure
+// the child processes' locked memory, and which field of the child processes'
+// "status" files is measured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    // The time to sleep between calls to `run_ps`.
+    pub discovery_interval: Duration,
+    // The time to sleep between reads of the child processes' "status"
+    // files.
+    pub measurement_interval: Duration,
+    // For this long after monitoring starts, `run_ps` is called every
+    // `FAST_PS_INTERVAL_MS` milliseconds (if that is shorter than
+    // `discovery_interval`).
+    pub fast_discovery_window: Duration,
+    // The "status" file field to measure in place of `VmLck`, e.g. `VmRSS`
+    // or `VmSwap`. Its value is recorded as the processes' locked memory.
+    pub field: String,
+    // Where the proc filesystem is mounted, e.g. to monitor the processes of
+    // another mount namespace.
+    pub procfs: String,
+    // Search for new child processes as soon as the proc connector reports
+    // that a known process forked or `exec`ed, rather than polling, if we
+    // are allowed to listen to it.
+    pub proc_events: bool,
+    // Stop monitoring once a child process whose name contains this has been
+    // found and has then exited.
+    pub stop_after: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            discovery_interval: Duration::from_millis(DEFAULT_PS_INTERVAL_MS),
+            measurement_interval: Duration::from_millis(DEFAULT_MEASUREMENT_INTERVAL_MS),
+            fast_discovery_window: Duration::from_millis(DEFAULT_FAST_PS_WINDOW_MS),
+            field: DEFAULT_FIELD.to_string(),
+            procfs: DEFAULT_PROCFS.to_string(),
+            proc_events: false,
+            stop_after: None,
+        }
+    }
+}
+
+// How much the worker threads log to stderr while monitoring, from least to
+// most.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Quiet,
+    // Log each change to the set of child processes, each new child process
+    // added to the database, and each "status" file that fails to parse.
+    Verbose,
+}
+
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(LogLevel::Quiet as usize);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn log_enabled(level: LogLevel) -> bool {
+    LOG_LEVEL.load(Ordering::Relaxed) >= level as usize
+}
+
+// Logs a message to stderr if `log_enabled($level)`.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        if log_enabled($level) {
+            eprintln!("[mlocktest] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+// By default, ignore child processes with the following names.
+pub const IGNORE_CHILD_PROCS: [&str; 3] = ["rustc", "[rustc]", "rustdoc"];
+
+pub type Pid = u32;
+pub type Pname = String;
+
+// The OS may reuse a pid once its process has exited, so processes are
+// identified by their pid and start time (measured in clock ticks since
+// boot).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcKey {
+    pub pid: Pid,
+    pub starttime: u64,
+}
+
+// A process found by `run_ps`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcEntry {
+    pub pid: Pid,
+    pub ppid: Pid,
+    pub pname: Pname,
+    // The process' full command line (see `read_cmdline`).
+    pub cmdline: String,
+    // In clock ticks since boot.
+    pub starttime: u64,
+}
+
+impl ProcEntry {
+    // Returns the key identifying the process in the measurements database.
+    pub fn key(&self) -> ProcKey {
+        ProcKey { pid: self.pid, starttime: self.starttime }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Pinfo {
+    pub pname: Pname,
+    // The process' full command line, with its arguments separated by
+    // spaces.
+    pub cmdline: String,
+    pub max_locked: u64,
+    // The min locked memory observed, in kbs.
+    pub min_locked: u64,
+    // The sum of the locked memory of every sample taken of this process and
+    // the number of samples, used to calculate its average locked memory.
+    pub total_locked: u64,
+    pub n_samples: u64,
+    // The locked memory of the most recent sample, in kbs.
+    pub last_locked: u64,
+    // Whether the locked memory was seen returning to zero after being
+    // nonzero, e.g. because the process munlocked its secrets.
+    pub released: bool,
+    // The max resident set size (`VmRSS`) observed, in kbs.
+    pub max_rss: u64,
+    // The max peak resident set size (`VmHWM`) observed, in kbs.
+    pub max_hwm: u64,
+    // The (time since monitoring started, kbs locked) samples for this
+    // process. Only recorded when running with `--timeseries`.
+    pub timeseries: Vec<(Duration, u64)>,
+    // The max locked memory observed for each of the process' threads, by
+    // thread id. Only recorded when running with `--per-thread`.
+    pub threads: BTreeMap<Pid, u64>,
+}
+
+impl Pinfo {
+    // Returns the average locked memory over every sample taken of this
+    // process, in kbs.
+    pub fn avg_locked(&self) -> u64 {
+        self.total_locked.checked_div(self.n_samples).unwrap_or(0)
+    }
+
+    // Merges another processes' measurements into this one, e.g. to report
+    // every process with the same name as a single row.
+    pub fn merge(&mut self, other: &Pinfo) {
+        self.max_locked = self.max_locked.max(other.max_locked);
+        let other_is_lower = other.n_samples > 0 && other.min_locked < self.min_locked;
+        if self.n_samples == 0 || other_is_lower {
+            self.min_locked = other.min_locked;
+        }
+        self.total_locked += other.total_locked;
+        self.n_samples += other.n_samples;
+        if other.n_samples > 0 {
+            self.last_locked = other.last_locked;
+        }
+        self.released |= other.released;
+        self.max_rss = self.max_rss.max(other.max_rss);
+        self.max_hwm = self.max_hwm.max(other.max_hwm);
+        self.timeseries.extend_from_slice(&other.timeseries);
+        for (tid, max_locked) in &other.threads {
+            let thread = self.threads.entry(*tid).or_insert(0);
+            *thread = (*thread).max(*max_locked);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Database {
+    pub pinfos: BTreeMap<ProcKey, Pinfo>,
+    // The max total ammount of memory locked at the same time by all child
+    // processes.
+    pub max_total_locked: u64,
+    // The names of the child processes that were found but ignored.
+    pub ignored: BTreeMap<ProcKey, String>,
+    // The number of measurement sweeps that started each number of
+    // microseconds after the previous sweep.
+    pub sweep_intervals: BTreeMap<u64, u64>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Database::new()
+    }
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            pinfos: BTreeMap::new(),
+            max_total_locked: 0,
+            ignored: BTreeMap::new(),
+            sweep_intervals: BTreeMap::new(),
+        }
+    }
+
+    // Returns the max locked memory of the child process that locked the most
+    // memory.
+    pub fn max_locked(&self) -> u64 {
+        self.pinfos.values().map(|pinfo| pinfo.max_locked).max().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pinfos.is_empty()
+    }
+
+    pub fn contains(&self, key: &ProcKey) -> bool {
+        self.pinfos.contains_key(key)
+    }
+
+    pub fn new_child_process(&mut self, key: ProcKey, pname: Pname, cmdline: String) {
+        self.pinfos.insert(key, Pinfo {
+            pname,
+            cmdline,
+            max_locked: 0,
+            min_locked: 0,
+            total_locked: 0,
+            n_samples: 0,
+            last_locked: 0,
+            released: false,
+            max_rss: 0,
+            max_hwm: 0,
+            timeseries: vec![],
+            threads: BTreeMap::new(),
+        });
+    }
+
+    pub fn update(&mut self, key: ProcKey, status: &Status) {
+        if let Some(pinfo) = self.pinfos.get_mut(&key) {
+            if status.vmlck > pinfo.max_locked {
+                pinfo.max_locked = status.vmlck;
+            }
+            if pinfo.n_samples == 0 || status.vmlck < pinfo.min_locked {
+                pinfo.min_locked = status.vmlck;
+            }
+            pinfo.total_locked += status.vmlck;
+            pinfo.n_samples += 1;
+            if status.vmlck == 0 && pinfo.last_locked > 0 {
+                pinfo.released = true;
+            }
+            pinfo.last_locked = status.vmlck;
+            if status.vmrss > pinfo.max_rss {
+                pinfo.max_rss = status.vmrss;
+            }
+            if status.vmhwm > pinfo.max_hwm {
+                pinfo.max_hwm = status.vmhwm;
+            }
+        }
+    }
+
+    // Like `update`, but first adds the child process if it isn't in the
+    // database yet. The ps thread publishes the pids it found before adding
+    // them to the database, so the measurements thread may measure a process
+    // first; rather than dropping its sample, it is named here the same way
+    // the ps thread would (falling back to its "comm" file).
+    pub fn update_or_insert(&mut self, procfs: &str, key: ProcKey, status: &Status) {
+        if !self.contains(&key) {
+            let pname = match read_pname(procfs, key.pid) {
+                Some(pname) => pname,
+                // The process has already exited.
+                None => return,
+            };
+            let cmdline = read_cmdline(procfs, key.pid, &pname);
+            log!(LogLevel::Verbose, "new child process (pid {}): {}", key.pid, cmdline);
+            self.new_child_process(key, pname, cmdline);
+        }
+        self.update(key, status);
+    }
+
+    // Updates the max locked memory of one of a child processes' threads.
+    pub fn update_thread(&mut self, key: ProcKey, tid: Pid, kbs_locked: u64) {
+        if let Some(pinfo) = self.pinfos.get_mut(&key) {
+            let max_locked = pinfo.threads.entry(tid).or_insert(0);
+            if kbs_locked > *max_locked {
+                *max_locked = kbs_locked;
+            }
+        }
+    }
+
+    // Records a time series sample for a child process.
+    pub fn record_sample(&mut self, key: ProcKey, elapsed: Duration, kbs_locked: u64) {
+        if let Some(pinfo) = self.pinfos.get_mut(&key) {
+            pinfo.timeseries.push((elapsed, kbs_locked));
+        }
+    }
+
+    // Merges the measurements of another run into this database. Processes
+    // present in both databases are merged, e.g. taking the max of their max
+    // locked memory, and those only present in `other` are added.
+    pub fn merge(&mut self, other: Database) {
+        for (key, pinfo) in other.pinfos {
+            match self.pinfos.get_mut(&key) {
+                Some(existing) => existing.merge(&pinfo),
+                None => {
+                    self.pinfos.insert(key, pinfo);
+                }
+            }
+        }
+        self.update_total(other.max_total_locked);
+        self.ignored.extend(other.ignored);
+        for (interval, count) in other.sweep_intervals {
+            *self.sweep_intervals.entry(interval).or_insert(0) += count;
+        }
+    }
+
+    // Merges the measurements of another run of the same command into this
+    // database, so that each process' worst case across the runs is reported.
+    // Processes get new pids in each run, so they are matched by name: the
+    // nth process with a given name (in the order they were started) in
+    // `other` is merged into the nth one in this database. Processes without
+    // a match are added as with `merge`.
+    pub fn merge_run(&mut self, mut other: Database) {
+        let by_start = |pinfos: &BTreeMap<ProcKey, Pinfo>| {
+            let mut keys: Vec<ProcKey> = pinfos.keys().copied().collect();
+            keys.sort_by_key(|key| (key.starttime, key.pid));
+            keys
+        };
+        // Reversed, so that popping a name's keys gives them in start order.
+        let mut by_name: HashMap<Pname, Vec<ProcKey>> = HashMap::new();
+        for key in by_start(&self.pinfos).into_iter().rev() {
+            by_name.entry(self.pinfos[&key].pname.clone()).or_default().push(key);
+        }
+        for key in by_start(&other.pinfos) {
+            let matched = by_name.get_mut(&other.pinfos[&key].pname).and_then(Vec::pop);
+            let existing = matched.and_then(|matched| self.pinfos.get_mut(&matched));
+            if let Some(existing) = existing {
+                existing.merge(&other.pinfos.remove(&key).unwrap());
+            }
+        }
+        self.merge(other);
+    }
+
+    // Records the time between the starts of two consecutive measurement
+    // sweeps.
+    pub fn record_sweep_interval(&mut self, interval: Duration) {
+        *self.sweep_intervals.entry(interval.as_micros() as u64).or_insert(0) += 1;
+    }
+
+    // Returns the median and the max time between consecutive measurement
+    // sweeps, i.e. the resolution that the measurements actually achieved,
+    // or `None` if fewer than two sweeps were taken.
+    pub fn sweep_resolution(&self) -> Option<(Duration, Duration)> {
+        let n_intervals: u64 = self.sweep_intervals.values().sum();
+        let max = *self.sweep_intervals.keys().next_back()?;
+        let mut n_seen = 0;
+        let (median, _count) = self.sweep_intervals.iter().find(|(_interval, count)| {
+            n_seen += **count;
+            n_seen * 2 >= n_intervals
+        })?;
+        Some((Duration::from_micros(*median), Duration::from_micros(max)))
+    }
+
+    // Describes how many child processes were observed, how many of them were
+    // ignored (by name), and how many of them are reported (`n_reported`,
+    // e.g. after filtering the database).
+    pub fn census(&self, n_reported: usize) -> String {
+        let n_observed = self.pinfos.len() + self.ignored.len();
+        let noun = if n_observed == 1 { "process" } else { "processes" };
+        let mut ignored_by_name: BTreeMap<&str, usize> = BTreeMap::new();
+        for pname in self.ignored.values() {
+            *ignored_by_name.entry(pname).or_insert(0) += 1;
+        }
+        if ignored_by_name.is_empty() {
+            return format!("Observed {} {}, {} reported", n_observed, noun, n_reported);
+        }
+        let ignored: Vec<String> = ignored_by_name
+            .iter()
+            .map(|(pname, count)| format!("{} {}", count, pname))
+            .collect();
+        format!(
+            "Observed {} {} ({} ignored), {} reported",
+            n_observed,
+            noun,
+            ignored.join(", "),
+            n_reported,
+        )
+    }
+
+    // Updates the max total locked memory using the sum of the memory locked
+    // by each child process during a single measurement sweep.
+    pub fn update_total(&mut self, total_kbs_locked: u64) {
+        if total_kbs_locked > self.max_total_locked {
+            self.max_total_locked = total_kbs_locked;
+        }
+    }
+
+    // Returns the child processes ordered by `sort`.
+    pub fn sorted(&self, sort: Sort) -> Vec<(&ProcKey, &Pinfo)> {
+        let mut pinfos: Vec<(&ProcKey, &Pinfo)> = self.pinfos.iter().collect();
+        match sort {
+            Sort::Locked => pinfos.sort_by(|(_, a), (_, b)| {
+                b.max_locked.cmp(&a.max_locked).then_with(|| a.pname.cmp(&b.pname))
+            }),
+            Sort::Name => pinfos.sort_by(|(_, a), (_, b)| a.pname.cmp(&b.pname)),
+            // The database is already ordered by pid.
+            Sort::Pid => {}
+        };
+        pinfos
+    }
+
+    // Formats the database as a table using `options.renderer`. The third
+    // column shows each child processes' max locked memory as a percentage of
+    // the hard limit. See `TableOptions` for the columns and rows that can be
+    // added.
+    pub fn table(&self, hard_limit: &Limit, options: &TableOptions) -> String {
+        self.to_table(hard_limit, options)
+            .render_with(options.renderer, &options.style)
+    }
+
+    // Builds the headings and rows of the table, ready to be rendered.
+    pub fn to_table(&self, hard_limit: &Limit, options: &TableOptions) -> Table {
+        let raw_kb = options.raw_kb;
+        // The locked memory limits only apply when measuring `VmLck`.
+        let locked = options.field == DEFAULT_FIELD;
+        let heading = |heading: &str| {
+            if raw_kb { format!("{} (kb)", heading) } else { heading.to_string() }
+        };
+        let mut headings = vec![
+            if options.full_cmd { "Command" } else { "Process Name" }.to_string(),
+        ];
+        if locked {
+            headings.push(heading("Max Locked Memory"));
+            headings.push("% of hard limit".to_string());
+        } else {
+            headings.push(heading(&format!("Max {}", options.field)));
+        }
+        if options.group_by_name {
+            headings.push("PIDs".to_string());
+        }
+        if options.stats && locked {
+            headings.push(heading("Min Locked"));
+            headings.push(heading("Avg Locked"));
+        } else if options.stats {
+            headings.push(heading(&format!("Min {}", options.field)));
+            headings.push(heading(&format!("Avg {}", options.field)));
+        }
+        if options.show_rss {
+            headings.push(heading("Max RSS"));
+            headings.push(heading("Max HWM"));
+        }
+        if options.show_samples {
+            headings.push("Samples".to_string());
+        }
+        if options.show_released {
+            headings.push("Released".to_string());
+        }
+        let format_mem = |kbs: u64| {
+            if raw_kb { kbs.to_string() } else { format_kbs(kbs) }
+        };
+        let percent_of_hard = |kbs: u64| match hard_limit {
+            Limit::Kb(hard_kbs) if *hard_kbs > 0 => {
+                (kbs.saturating_mul(100) / hard_kbs).to_string()
+            }
+            _ => "n/a".to_string(),
+        };
+        let name = |pinfo: &Pinfo| {
+            if options.full_cmd { pinfo.cmdline.clone() } else { pinfo.pname.clone() }
+        };
+        // Names are only truncated for display, so that two processes whose
+        // names share a prefix aren't grouped together.
+        let display_name = |pinfo: &Pinfo| {
+            let name = name(pinfo);
+            match options.name_width {
+                Some(width) if name.chars().count() > width => {
+                    let mut truncated: String =
+                        name.chars().take(width.saturating_sub(1)).collect();
+                    truncated.push('…');
+                    truncated
+                }
+                _ => name,
+            }
+        };
+        // Each entry is a row's (measurements, number of pids). Grouping
+        // keeps the order of each name's first process, which for every
+        // `Sort` is the order of the merged rows.
+        let mut entries: Vec<(Cow<Pinfo>, usize)> = vec![];
+        for (_key, pinfo) in self.sorted(options.sort) {
+            let group = if options.group_by_name {
+                entries.iter_mut().find(|(entry, _)| name(entry) == name(pinfo))
+            } else {
+                None
+            };
+            match group {
+                Some((entry, n_pids)) => {
+                    entry.to_mut().merge(pinfo);
+                    *n_pids += 1;
+                }
+                None => entries.push((Cow::Borrowed(pinfo), 1)),
+            }
+        }
+        if options.hide_zero {
+            entries.retain(|(pinfo, _n_pids)| pinfo.max_locked > 0);
+        }
+        let mut n_hidden = 0;
+        if let Some(top) = options.top {
+            // Only show the `top` rows which locked the most memory, still
+            // ordered by `sort`.
+            let mut by_locked: Vec<usize> = (0..entries.len()).collect();
+            by_locked.sort_by(|a, b| {
+                let (a, b) = (&entries[*a].0, &entries[*b].0);
+                b.max_locked.cmp(&a.max_locked).then_with(|| a.pname.cmp(&b.pname))
+            });
+            by_locked.truncate(top);
+            let top_rows: BTreeSet<usize> = by_locked.into_iter().collect();
+            n_hidden = entries.len() - top_rows.len();
+            entries = entries
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| top_rows.contains(i))
+                .map(|(_, entry)| entry)
+                .collect();
+        }
+        let mut rows: Vec<Vec<String>> = vec![];
+        let mut colors = vec![];
+        let warn_percent = options.warn_percent;
+        for (pinfo, n_pids) in entries {
+            let mut row = vec![display_name(&pinfo), format_mem(pinfo.max_locked)];
+            if locked {
+                row.push(percent_of_hard(pinfo.max_locked));
+            }
+            if options.group_by_name {
+                row.push(n_pids.to_string());
+            }
+            if options.stats {
+                row.push(format_mem(pinfo.min_locked));
+                row.push(format_mem(pinfo.avg_locked()));
+            }
+            if options.show_rss {
+                row.push(format_mem(pinfo.max_rss));
+                row.push(format_mem(pinfo.max_hwm));
+            }
+            if options.show_samples {
+                row.push(pinfo.n_samples.to_string());
+            }
+            if options.show_released {
+                // Only a process that locked memory can release it.
+                let released = match (pinfo.max_locked, pinfo.released) {
+                    (0, _) => "n/a",
+                    (_, true) => "yes",
+                    (_, false) => "no",
+                };
+                row.push(released.to_string());
+            }
+            rows.push(row);
+            // Rows that come close to, or reach, the hard limit are
+            // highlighted.
+            let verdict = Verdict::check_with(pinfo.max_locked, hard_limit, warn_percent);
+            let color = match verdict {
+                Some(Verdict::Pass) | None => None,
+                Some(verdict) => Some(verdict.color()),
+            };
+            colors.push(if options.color && locked { color } else { None });
+            // Each thread gets its own row below its process; we only
+            // measure the threads' locked memory.
+            if options.per_thread {
+                for (tid, max_locked) in &pinfo.threads {
+                    let mut row = vec![format!("  tid {}", tid), format_mem(*max_locked)];
+                    if locked {
+                        row.push(percent_of_hard(*max_locked));
+                    }
+                    row.resize(headings.len(), String::new());
+                    rows.push(row);
+                    colors.push(None);
+                }
+            }
+        }
+        Table {
+            headings,
+            rows,
+            colors,
+            n_hidden,
+        }
+    }
+
+    // Returns the child processes whose max locked memory exceeded
+    // `max_locked_kb`.
+    pub fn exceeding(&self, max_locked_kb: u64) -> Vec<(&ProcKey, &Pinfo)> {
+        self.pinfos
+            .iter()
+            .filter(|(_key, pinfo)| pinfo.max_locked > max_locked_kb)
+            .collect()
+    }
+
+    // Serializes the database into a JSON array containing one object per
+    // child process.
+    pub fn to_json(&self) -> String {
+        self.json_value().to_string()
+    }
+
+    // Like `to_json`, but indented for reading, e.g. in a pull request.
+    pub fn to_json_pretty(&self) -> String {
+        serde_json::to_string_pretty(&self.json_value()).unwrap()
+    }
+
+    fn json_value(&self) -> serde_json::Value {
+        let pinfos: Vec<serde_json::Value> = self.pinfos
+            .iter()
+            .map(|(key, Pinfo { pname, max_locked, .. })| json!({
+                "pid": key.pid,
+                "starttime": key.starttime,
+                "pname": pname,
+                "max_locked": max_locked,
+            }))
+            .collect();
+        serde_json::Value::Array(pinfos)
+    }
+
+    // Parses a report written by `to_json`, e.g. a previous run's report to
+    // compare against. Only the fields written by `to_json` are restored.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let invalid = |e: &dyn Display| format!("invalid JSON report: {}", e);
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| invalid(&e))?;
+        let pinfos = value.as_array().ok_or_else(|| invalid(&"expected an array"))?;
+        let mut db = Database::new();
+        for pinfo in pinfos {
+            let field = |name: &str| {
+                pinfo.get(name).ok_or_else(|| invalid(&format!("missing `{}`", name)))
+            };
+            let as_u64 = |name: &str| {
+                let not_a_number = || invalid(&format!("`{}` isn't a number", name));
+                field(name)?.as_u64().ok_or_else(not_a_number)
+            };
+            let pname = field("pname")?
+                .as_str()
+                .ok_or_else(|| invalid(&"`pname` isn't a string"))?
+                .to_string();
+            let key = ProcKey { pid: as_u64("pid")? as Pid, starttime: as_u64("starttime")? };
+            db.new_child_process(key, pname.clone(), pname);
+            if let Some(pinfo) = db.pinfos.get_mut(&key) {
+                pinfo.max_locked = as_u64("max_locked")?;
+            }
+        }
+        Ok(db)
+    }
+
+    // Returns a copy of the database with only the processes whose name or
+    // command line contains `substring`. The max total locked memory is kept,
+    // as it is the total of every process that was monitored.
+    pub fn filtered(&self, substring: &str) -> Database {
+        let pinfos = self
+            .pinfos
+            .iter()
+            .filter(|(_key, pinfo)| {
+                pinfo.pname.contains(substring) || pinfo.cmdline.contains(substring)
+            })
+            .map(|(key, pinfo)| (*key, pinfo.clone()))
+            .collect();
+        Database {
+            pinfos,
+            max_total_locked: self.max_total_locked,
+            ignored: self.ignored.clone(),
+            sweep_intervals: self.sweep_intervals.clone(),
+        }
+    }
+
+    // Returns the max locked memory of the processes with each name.
+    pub fn max_locked_by_name(&self) -> BTreeMap<&str, u64> {
+        let mut by_name = BTreeMap::new();
+        for pinfo in self.pinfos.values() {
+            let max_locked = by_name.entry(pinfo.pname.as_str()).or_insert(0);
+            *max_locked = pinfo.max_locked.max(*max_locked);
+        }
+        by_name
+    }
+
+    // Compares each process' max locked memory against a `baseline` run.
+    // Pids differ between runs, so processes are matched by name.
+    pub fn compare(&self, baseline: &Database) -> Vec<Delta> {
+        let current = self.max_locked_by_name();
+        let baseline = baseline.max_locked_by_name();
+        let pnames: BTreeSet<&str> =
+            current.keys().chain(baseline.keys()).cloned().collect();
+        pnames
+            .into_iter()
+            .map(|pname| Delta {
+                pname: pname.to_string(),
+                baseline: baseline.get(pname).cloned(),
+                current: current.get(pname).cloned(),
+            })
+            .collect()
+    }
+
+    // Serializes the database into CSV rows of the form:
+    // `pid,pname,max_locked_kb`, preceded by a header row. Rows are ordered
+    // by pid so that the output of different runs can be diffed.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("pid,pname,max_locked_kb\n");
+        for (key, Pinfo { pname, max_locked, .. }) in &self.pinfos {
+            csv.push_str(&format!("{},{},{}\n", key.pid, csv_field(pname), max_locked));
+        }
+        csv
+    }
+
+    // Serializes each child processes' time series samples into CSV rows of
+    // the form: `pid,pname,elapsed_ms,kbs_locked`.
+    pub fn to_csv_timeseries(&self) -> String {
+        let mut csv = String::from("pid,pname,elapsed_ms,kbs_locked\n");
+        for (key, Pinfo { pname, timeseries, .. }) in &self.pinfos {
+            for (elapsed, kbs_locked) in timeseries {
+                let row = format!(
+                    "{},{},{},{}\n",
+                    key.pid,
+                    csv_field(pname),
+                    elapsed.as_millis(),
+                    kbs_locked,
+                );
+                csv.push_str(&row);
+            }
+        }
+        csv
+    }
+}
+
+// The change in the max locked memory of the processes with a given name
+// between a baseline run and the current run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delta {
+    pub pname: Pname,
+    // The max locked memory in each run, `None` if no process with this name
+    // was observed in that run.
+    pub baseline: Option<u64>,
+    pub current: Option<u64>,
+}
+
+impl Delta {
+    pub fn change(&self) -> Change {
+        match (self.baseline, self.current) {
+            (Some(baseline), Some(current)) if current > baseline => Change::Increased,
+            (Some(baseline), Some(current)) if current < baseline => Change::Decreased,
+            (Some(_), Some(_)) => Change::Unchanged,
+            (Some(_), None) => Change::Removed,
+            (None, _) => Change::New,
+        }
+    }
+
+    // Returns how many more kbs were locked than in the baseline run. New
+    // processes aren't counted as an increase, as their names (e.g. test
+    // binaries' hashes) often change between builds.
+    pub fn increase(&self) -> u64 {
+        match (self.baseline, self.current) {
+            (Some(baseline), Some(current)) => current.saturating_sub(baseline),
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    New,
+    Removed,
+    Increased,
+    Decreased,
+    Unchanged,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Change::New => write!(f, "new"),
+            Change::Removed => write!(f, "removed"),
+            Change::Increased => write!(f, "increased"),
+            Change::Decreased => write!(f, "decreased"),
+            Change::Unchanged => write!(f, "unchanged"),
+        }
+    }
+}
+
+// Formats a number of kbs using the largest unit (KiB, MiB, or GiB) in which
+// the value is at least 1, e.g. "4.5 MiB".
+pub fn format_kbs(kbs: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    let mut value = kbs as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// The contents of the measurements table, which can be rendered in any of the
+// `Renderer` formats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Table {
+    pub headings: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    // The color of each row, if any. Only used by `Renderer::Ascii`; rows
+    // without an entry aren't colored.
+    pub colors: Vec<Option<Color>>,
+    // The number of processes left out of the table by `TableOptions::top`.
+    pub n_hidden: usize,
+}
+
+impl Table {
+    pub fn render(&self, renderer: Renderer) -> String {
+        self.render_with(renderer, &TableStyle::default())
+    }
+
+    // Renders the table, laying out `Renderer::Ascii` tables using `style`.
+    pub fn render_with(&self, renderer: Renderer, style: &TableStyle) -> String {
+        let mut table = match renderer {
+            Renderer::Ascii => {
+                format_table(&self.headings, &self.rows, &self.colors, style)
+            }
+            Renderer::Markdown => format_markdown_table(&self.headings, &self.rows),
+        };
+        if self.n_hidden > 0 {
+            // A Markdown table only ends at a blank line.
+            if renderer == Renderer::Markdown {
+                table.push('\n');
+            }
+            table.push_str(&format!("\n... and {} more", self.n_hidden));
+        }
+        table
+    }
+}
+
+// The terminal colors used to highlight the limit check and table rows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Green,
+    Yellow,
+    Red,
+}
+
+impl Color {
+    // Wraps `s` in the ANSI escape codes for this color.
+    pub fn paint(self, s: &str) -> String {
+        let code = match self {
+            Color::Green => 32,
+            Color::Yellow => 33,
+            Color::Red => 31,
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, s)
+    }
+}
+
+// How a `Table` is formatted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Renderer {
+    // A fixed-width table for terminals.
+    Ascii,
+    // A GitHub flavored Markdown table, e.g. for pasting into pull requests.
+    Markdown,
+}
+
+// How a `Renderer::Ascii` table is laid out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TableStyle {
+    // The minimum number of spaces between columns.
+    pub column_gap: usize,
+    // The character used to underline the headings and end the table.
+    pub border: char,
+    // Whether to draw the borders at all.
+    pub borders: bool,
+}
+
+pub const DEFAULT_TABLE_STYLE: TableStyle = TableStyle {
+    column_gap: COLUMN_BUFFER,
+    border: '=',
+    borders: true,
+};
+
+impl Default for TableStyle {
+    fn default() -> Self {
+        DEFAULT_TABLE_STYLE
+    }
+}
+
+// Formats a GitHub flavored Markdown table. Pipes within cells are escaped,
+// and leading whitespace (used to indent thread rows) is kept by using
+// non-breaking spaces, as Markdown would otherwise collapse it.
+fn format_markdown_table(headings: &[String], rows: &[Vec<String>]) -> String {
+    let format_line = |cells: &[String]| -> String {
+        let cells: Vec<String> = cells
+            .iter()
+            .map(|cell| {
+                let trimmed = cell.trim_start();
+                let indent = "&nbsp;".repeat(cell.len() - trimmed.len());
+                format!("{}{}", indent, trimmed.replace('|', "\\|"))
+            })
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let separator: Vec<String> = headings.iter().map(|_| "---".to_string()).collect();
+    let mut markdown = format!("{}\n{}", format_line(headings), format_line(&separator));
+    for row in rows {
+        markdown.push('\n');
+        markdown.push_str(&format_line(row));
+    }
+    markdown
+}
+
+// Formats a fixed-width table where each column is separated by at least
+// `style.column_gap` spaces. Colors are applied to whole lines so that the
+// escape codes don't throw off the column widths.
+fn format_table(
+    headings: &[String],
+    rows: &[Vec<String>],
+    colors: &[Option<Color>],
+    style: &TableStyle,
+) -> String {
+    let n_chars = |s: &str| s.chars().count();
+    let widths: Vec<usize> = headings
+        .iter()
+        .enumerate()
+        .map(|(i, heading)| {
+            rows.iter().fold(n_chars(heading), |longest, row| {
+                match n_chars(&row[i]) {
+                    n_chars if n_chars > longest => n_chars,
+                    _ => longest,
+                }
+            })
+        })
+        .collect();
+    let format_line = |cells: &[String]| -> String {
+        let last = cells.len() - 1;
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            line.push_str(cell);
+            if i < last {
+                let whitespace = widths[i] + style.column_gap - n_chars(cell);
+                line.push_str(&" ".repeat(whitespace));
+            }
+        }
+        line
+    };
+    let border = style.border.to_string();
+    let mut stdout = format!("\n{}\n", format_line(headings));
+    if style.borders {
+        let top_border: Vec<String> = headings
+            .iter()
+            .map(|heading| border.repeat(n_chars(heading)))
+            .collect();
+        stdout.push_str(&format_line(&top_border));
+        stdout.push('\n');
+    }
+    for (i, row) in rows.iter().enumerate() {
+        match colors.get(i).cloned().flatten() {
+            Some(color) => stdout.push_str(&color.paint(&format_line(row))),
+            None => stdout.push_str(&format_line(row)),
+        }
+        stdout.push('\n');
+    }
+    if style.borders {
+        let table_width =
+            widths.iter().sum::<usize>() + style.column_gap * (widths.len() - 1);
+        stdout.push_str(&border.repeat(table_width));
+    } else {
+        // Without a bottom border the table ends at its last row.
+        stdout.pop();
+    }
+    stdout
+}
+
+// Quotes a CSV field if it contains a comma, quote, or whitespace.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c.is_whitespace()) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// The options for formatting the measurements table.
+#[derive(Clone, Copy, Debug)]
+pub struct TableOptions<'a> {
+    pub sort: Sort,
+    // The "status" file field that was measured (see `Config::field`).
+    pub field: &'a str,
+    // Show memory in kbs rather than in human readable units.
+    pub raw_kb: bool,
+    // Show the min and average locked memory of each process.
+    pub stats: bool,
+    // Show the max `VmRSS` and `VmHWM` of each process.
+    pub show_rss: bool,
+    // Show the number of samples taken of each process. A process with few
+    // (or no) samples may have locked more memory than was measured.
+    pub show_samples: bool,
+    // Show whether each processes' locked memory returned to zero after
+    // being nonzero.
+    pub show_released: bool,
+    // Leave out the processes that never locked any memory.
+    pub hide_zero: bool,
+    // Show each processes' full command line rather than its name.
+    pub full_cmd: bool,
+    // Truncate the names (or command lines) longer than this many characters,
+    // ending them with an ellipsis.
+    pub name_width: Option<usize>,
+    // Show the max locked memory of each processes' threads.
+    pub per_thread: bool,
+    // Only show this many of the processes which locked the most memory.
+    pub top: Option<usize>,
+    // Color the rows of the processes that locked at least `warn_percent`
+    // of the hard limit yellow, and those that reached it red.
+    pub color: bool,
+    pub warn_percent: u64,
+    // Merge the processes with the same name into a single row, showing how
+    // many pids shared that name.
+    pub group_by_name: bool,
+    pub renderer: Renderer,
+    pub style: TableStyle,
+}
+
+impl Default for TableOptions<'static> {
+    fn default() -> Self {
+        TableOptions {
+            sort: Sort::Locked,
+            field: DEFAULT_FIELD,
+            raw_kb: false,
+            stats: false,
+            show_rss: false,
+            show_samples: false,
+            show_released: false,
+            hide_zero: false,
+            full_cmd: false,
+            name_width: None,
+            per_thread: false,
+            top: None,
+            color: false,
+            warn_percent: LIMIT_WARN_PERCENT,
+            group_by_name: false,
+            renderer: Renderer::Ascii,
+            style: TableStyle::default(),
+        }
+    }
+}
+
+// The order of the rows in the measurements table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    // Descending by max locked memory.
+    Locked,
+    Name,
+    Pid,
+}
+
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "locked" => Ok(Sort::Locked),
+            "name" => Ok(Sort::Name),
+            "pid" => Ok(Sort::Pid),
+            _ => Err(format!("invalid `--sort`: `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Limit {
+    Kb(u64),
+    Unlimited,
+}
+
+// Formats the limit in kbs, or with the alternate flag (`{:#}`) in human
+// readable units like `format_kbs`, e.g. "16.0 MiB".
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Limit::Kb(kbs) if f.alternate() => write!(f, "{}", format_kbs(*kbs)),
+            Limit::Kb(kbs) => write!(f, "{}", kbs),
+            _ => write!(f, "unlimited"),
+        }
+    }
+}
+
+// Parses a limit given in bytes (or "unlimited"). Like `getrlimit`'s limits,
+// the number of bytes is rounded down to whole kbs, as a process can't lock
+// part of a kb.
+impl FromStr for Limit {
+    type Err = LimitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "unlimited" {
+            Ok(Limit::Unlimited)
+        } else {
+            let n_bytes: u64 = s
+                .parse::<u64>()
+                .map_err(|_| LimitError::Parse(s.to_string()))?;
+            Ok(Limit::Kb(n_bytes / 1024))
+        }
+    }
+}
+
+impl Limit {
+    // Converts an `rlimit` value (in bytes) into a `Limit`. The cast is
+    // required on targets where `rlim_t` is 32 bits.
+    #[cfg(target_os = "linux")]
+    #[allow(clippy::unnecessary_cast)]
+    pub fn from_rlim(rlim: libc::rlim_t) -> Self {
+        if rlim == libc::RLIM_INFINITY {
+            Limit::Unlimited
+        } else {
+            Limit::Kb(rlim as u64 / 1024)
+        }
+    }
+}
+
+// The errors that can occur while getting the locked memory limits.
+#[derive(Debug)]
+pub enum LimitError {
+    // A limit was neither a number of bytes nor "unlimited".
+    Parse(String),
+    // The `getrlimit` syscall failed.
+    Getrlimit(io::Error),
+}
+
+impl Display for LimitError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            LimitError::Parse(s) => write!(f, "invalid locked memory limit: `{}`", s),
+            LimitError::Getrlimit(e) => write!(f, "`getrlimit` failed: {}", e),
+        }
+    }
+}
+
+impl Error for LimitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LimitError::Parse(_) => None,
+            LimitError::Getrlimit(e) => Some(e),
+        }
+    }
+}
+
+// Whether a process came close to, or reached, a locked memory limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "PASS"),
+            Verdict::Warn => write!(f, "WARN"),
+            Verdict::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+impl Verdict {
+    pub fn color(self) -> Color {
+        match self {
+            Verdict::Pass => Color::Green,
+            Verdict::Warn => Color::Yellow,
+            Verdict::Fail => Color::Red,
+        }
+    }
+
+    // Compares the max memory locked by a process against a limit. Reaching
+    // `LIMIT_WARN_PERCENT` of the limit is a warning, reaching the limit
+    // itself (after which `mlock` fails) is a failure. Returns `None` for
+    // unlimited limits.
+    pub fn check(max_locked: u64, limit: &Limit) -> Option<Verdict> {
+        Verdict::check_with(max_locked, limit, LIMIT_WARN_PERCENT)
+    }
+
+    // Like `check`, but warns once `warn_percent` of the limit is reached.
+    pub fn check_with(
+        max_locked: u64,
+        limit: &Limit,
+        warn_percent: u64,
+    ) -> Option<Verdict> {
+        let limit_kbs = match limit {
+            Limit::Kb(kbs) => *kbs,
+            Limit::Unlimited => return None,
+        };
+        let warn_at = limit_kbs.saturating_mul(warn_percent);
+        let verdict = if max_locked >= limit_kbs {
+            Verdict::Fail
+        } else if max_locked.saturating_mul(100) >= warn_at {
+            Verdict::Warn
+        } else {
+            Verdict::Pass
+        };
+        Some(verdict)
+    }
+}
+
+#[derive(Debug)]
+pub struct MlockLimit {
+    pub soft: Limit,
+    pub hard: Limit,
+}
+
+// Gets this processes' locked memory limit using `getrlimit`. Child processes
+// (e.g. `cargo test`) inherit this limit.
+#[cfg(target_os = "linux")]
+pub fn run_prlimit() -> Result<MlockLimit, LimitError> {
+    let mut rlimit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    let res = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlimit) };
+    if res != 0 {
+        return Err(LimitError::Getrlimit(io::Error::last_os_error()));
+    }
+    Ok(MlockLimit {
+        soft: Limit::from_rlim(rlimit.rlim_cur),
+        hard: Limit::from_rlim(rlimit.rlim_max),
+    })
+}
+
+// The capability that exempts a process from the locked memory limits.
+const CAP_IPC_LOCK: u32 = 14;
+
+// Returns whether this process has `CAP_IPC_LOCK` in its effective
+// capabilities, or `None` if they can't be read. Without it, `mlock` fails
+// with `EPERM` when the soft limit is 0 and with `ENOMEM` past the limit.
+pub fn has_cap_ipc_lock() -> Option<bool> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    parse_cap_ipc_lock(&status)
+}
+
+// Parses whether `CAP_IPC_LOCK` is set in the "CapEff" field (a hex bitmask)
+// of a "status" file.
+fn parse_cap_ipc_lock(status: &str) -> Option<bool> {
+    let caps = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    let caps = u64::from_str_radix(caps.trim(), 16).ok()?;
+    Some(caps & (1 << CAP_IPC_LOCK) != 0)
+}
+
+// Returns the CPU time (user and system) used by this process so far, e.g. to
+// measure the monitor's own overhead.
+pub fn read_cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    let ticks = parse_cpu_ticks(&stat)?;
+    let ticks_per_sec = clock_ticks_per_sec()?;
+    Some(Duration::from_secs_f64(ticks as f64 / ticks_per_sec as f64))
+}
+
+// Returns the number of clock ticks per second, the unit of the times in
+// "stat" files.
+#[cfg(unix)]
+fn clock_ticks_per_sec() -> Option<u64> {
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_sec <= 0 {
+        None
+    } else {
+        Some(ticks_per_sec as u64)
+    }
+}
+
+// There is no `sysconf` (nor `/proc`) elsewhere.
+#[cfg(not(unix))]
+fn clock_ticks_per_sec() -> Option<u64> {
+    None
+}
+
+// Parses the sum of a processes' user and system CPU time (fields 14 and 15,
+// in clock ticks) out of the contents of its "stat" file.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some(utime + stime)
+}
+
+// Parses a processes' parent pid and start time out of the contents of its
+// "stat" file. The process name (the second field) is wrapped in parentheses
+// and may itself contain spaces or parentheses, so we parse the fields
+// following the last closing parenthesis.
+fn parse_stat(stat: &str) -> Option<(Pid, u64)> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    // The fields following the process name start at field 3 (state); the
+    // parent pid is field 4 and the start time is field 22.
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    let ppid = fields.get(1)?.parse().ok()?;
+    let starttime = fields.get(19)?.parse().ok()?;
+    Some((ppid, starttime))
+}
+
+// Reads a processes' parent pid and start time from its "stat" file. A
+// "stat" file with fewer fields than expected is skipped (logging it under
+// `LogLevel::Verbose`) rather than stopping the discovery sweep.
+fn read_stat(procfs: &str, pid: Pid) -> Option<(Pid, u64)> {
+    let stat = fs::read_to_string(format!("{}/{}/stat", procfs, pid)).ok()?;
+    let parsed = parse_stat(&stat);
+    if parsed.is_none() {
+        log!(LogLevel::Verbose, "skipping pid {} with malformed stat {:?}", pid, stat);
+    }
+    parsed
+}
+
+// Reads a processes' name: the basename of the first argument in its
+// "cmdline" file. Falls back to the "comm" file for processes that have no
+// command line (e.g. zombie processes).
+fn read_pname(procfs: &str, pid: Pid) -> Option<Pname> {
+    let cmdline = fs::read(format!("{}/{}/cmdline", procfs, pid)).ok()?;
+    parse_pname(&cmdline).or_else(|| read_comm(procfs, pid))
+}
+
+// Reads a processes' name from its "comm" file.
+fn read_comm(procfs: &str, pid: Pid) -> Option<Pname> {
+    let comm = fs::read_to_string(format!("{}/{}/comm", procfs, pid)).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+// Parses the basename of the first argument out of the contents of a
+// "cmdline" file, or returns `None` if the command line is empty.
+fn parse_pname(cmdline: &[u8]) -> Option<Pname> {
+    let arg0 = cmdline.split(|byte| *byte == 0).next().unwrap_or(&[]);
+    if arg0.is_empty() {
+        return None;
+    }
+    let arg0 = String::from_utf8_lossy(arg0);
+    arg0.split('/').next_back().map(|basename| basename.to_string())
+}
+
+// Reads a processes' full command line from its "cmdline" file, joining its
+// (NUL separated) arguments with spaces. Falls back to the process' name for
+// processes that have no command line.
+fn read_cmdline(procfs: &str, pid: Pid, pname: &str) -> String {
+    let cmdline = fs::read(format!("{}/{}/cmdline", procfs, pid)).unwrap_or_default();
+    join_cmdline(&cmdline).unwrap_or_else(|| pname.to_string())
+}
+
+// Joins the arguments in the contents of a "cmdline" file with spaces, or
+// returns `None` if there are none.
+fn join_cmdline(cmdline: &[u8]) -> Option<String> {
+    let args: Vec<String> = cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    if args.is_empty() {
+        None
+    } else {
+        Some(args.join(" "))
+    }
+}
+
+// Reads the entry of the process `pid`, whose parent and start time were read
+// from its "stat" file. Its "cmdline" file is read once for both its name and
+// its command line.
+fn read_entry(procfs: &str, pid: Pid, ppid: Pid, starttime: u64) -> Option<ProcEntry> {
+    let cmdline = fs::read(format!("{}/{}/cmdline", procfs, pid)).ok()?;
+    let pname = parse_pname(&cmdline).or_else(|| read_comm(procfs, pid))?;
+    let cmdline = join_cmdline(&cmdline).unwrap_or_else(|| pname.clone());
+    Some(ProcEntry { pid, ppid, pname, cmdline, starttime })
+}
+
+// Walks `procfs` (usually `/proc`) to build a map from each process' pid to
+// its children. Processes whose files can't be read or parsed (e.g. because
+// the process exited during the walk) are left out of the map.
+fn read_process_tree(procfs: &str) -> HashMap<Pid, Vec<ProcKey>> {
+    let mut tree: HashMap<Pid, Vec<ProcKey>> = HashMap::new();
+    let entries = match fs::read_dir(procfs) {
+        Ok(entries) => entries,
+        Err(_) => return tree,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let pid: Pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process may exit while we are reading its files, in which case
+        // it is skipped.
+        if let Some((ppid, starttime)) = read_stat(procfs, pid) {
+            tree.entry(ppid).or_default().push(ProcKey { pid, starttime });
+        }
+    }
+    tree
+}
+
+// Finds all descendants of `cargo_test_pid` (children, grandchildren, etc.),
+// returning each descendant's entry. Processes named in `ignore` are
+// skipped along with all of their descendants. If `include_root` is set,
+// `cargo_test_pid` itself is also returned.
+pub fn run_ps(
+    cargo_test_pid: Pid,
+    include_root: bool,
+    ignore: &[String],
+) -> Vec<ProcEntry> {
+    run_ps_at(DEFAULT_PROCFS, cargo_test_pid, include_root, ignore)
+}
+
+// Like `run_ps`, but reads the processes from the proc filesystem mounted at
+// `procfs`.
+pub fn run_ps_at(
+    procfs: &str,
+    cargo_test_pid: Pid,
+    include_root: bool,
+    ignore: &[String],
+) -> Vec<ProcEntry> {
+    find_descendants(procfs, cargo_test_pid, include_root, ignore).0
+}
+
+// Like `run_ps`, but also returns the ignored processes (not including their
+// descendants).
+fn find_descendants(
+    procfs: &str,
+    cargo_test_pid: Pid,
+    include_root: bool,
+    ignore: &[String],
+) -> (Vec<ProcEntry>, Vec<ProcEntry>) {
+    let mut ps = vec![];
+    let mut ignored = vec![];
+    if include_root {
+        let root = read_stat(procfs, cargo_test_pid).and_then(|(ppid, starttime)| {
+            read_entry(procfs, cargo_test_pid, ppid, starttime)
+        });
+        ps.extend(root);
+    }
+    let tree = read_process_tree(procfs);
+    let mut unvisited = vec![cargo_test_pid];
+    while let Some(ppid) = unvisited.pop() {
+        for key in tree.get(&ppid).into_iter().flatten() {
+            if let Some(entry) = read_entry(procfs, key.pid, ppid, key.starttime) {
+                if ignore.contains(&entry.pname) {
+                    ignored.push(entry);
+                } else {
+                    unvisited.push(entry.pid);
+                    ps.push(entry);
+                }
+            }
+        }
+    }
+    (ps, ignored)
+}
+
+// The ids of the kernel's proc connector, the netlink channel which sends an
+// event whenever a process forks, `exec`s, or exits (see `linux/cn_proc.h`).
+#[cfg(target_os = "linux")]
+const CN_IDX_PROC: u32 = 1;
+#[cfg(target_os = "linux")]
+const CN_VAL_PROC: u32 = 1;
+#[cfg(target_os = "linux")]
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+#[cfg(target_os = "linux")]
+const PROC_EVENT_NONE: u32 = 0x0;
+#[cfg(target_os = "linux")]
+const PROC_EVENT_FORK: u32 = 0x1;
+#[cfg(target_os = "linux")]
+const PROC_EVENT_EXEC: u32 = 0x2;
+
+// The sizes of a netlink message's header and of a connector message's
+// header, which precede each process event.
+#[cfg(target_os = "linux")]
+const NLMSG_HDRLEN: usize = 16;
+#[cfg(target_os = "linux")]
+const CN_MSG_HDRLEN: usize = 20;
+
+// How long to wait for the kernel to acknowledge our subscription to the proc
+// connector. The kernel doesn't answer at all if we aren't allowed to listen.
+#[cfg(target_os = "linux")]
+const PROC_EVENTS_ACK_TIMEOUT: Duration = Duration::from_millis(100);
+
+// The process events that the ps thread acts on.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProcEvent {
+    // The kernel's answer to our subscription, with an errno if we aren't
+    // allowed to listen.
+    Ack { err: u32 },
+    // The process `parent` forked the process `child`.
+    Fork { parent: Pid, child: Pid },
+    // The process `exec`ed a new program.
+    Exec(Pid),
+}
+
+// A netlink socket subscribed to the proc connector, which lets the ps thread
+// search for new child processes as soon as they are started rather than
+// polling. Listening requires `CAP_NET_ADMIN`.
+#[cfg(target_os = "linux")]
+struct ProcEvents {
+    fd: libc::c_int,
+    buf: Vec<u8>,
+}
+
+#[cfg(target_os = "linux")]
+impl ProcEvents {
+    fn open() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The socket is closed on drop if we fail to subscribe.
+        let mut events = ProcEvents { fd, buf: vec![0; 4096] };
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = CN_IDX_PROC;
+        let res = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let msg = listen_message(process::id());
+        let msg_ptr = msg.as_ptr() as *const libc::c_void;
+        let res = unsafe { libc::send(fd, msg_ptr, msg.len(), 0) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let deadline = Instant::now() + PROC_EVENTS_ACK_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !events.poll(remaining) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the kernel didn't acknowledge the subscription",
+                ));
+            }
+            for event in events.recv()? {
+                if let ProcEvent::Ack { err } = event {
+                    if err != 0 {
+                        return Err(io::Error::from_raw_os_error(err as i32));
+                    }
+                    return Ok(events);
+                }
+            }
+        }
+    }
+
+    // Waits for up to `timeout` for an event which `is_relevant`. Returns
+    // early (as if such an event had arrived) if events are lost, e.g.
+    // because the socket's buffer overflowed.
+    fn wait(&mut self, timeout: Duration, is_relevant: impl Fn(&ProcEvent) -> bool) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.poll(remaining) {
+                return;
+            }
+            match self.recv() {
+                Ok(events) if !events.iter().any(&is_relevant) => {}
+                _ => return,
+            }
+        }
+    }
+
+    // Waits for up to `timeout` for an event to arrive. Returns `false` if
+    // none did (or if polling failed, after sleeping for `timeout`).
+    fn poll(&self, timeout: Duration) -> bool {
+        let mut pollfd = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+        let ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pollfd, 1, ms) } {
+            n if n > 0 => true,
+            0 => false,
+            _ => {
+                thread::sleep(timeout);
+                false
+            }
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<Vec<ProcEvent>> {
+        let buf = self.buf.as_mut_ptr() as *mut libc::c_void;
+        let n = unsafe { libc::recv(self.fd, buf, self.buf.len(), 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(parse_proc_events(&self.buf[..n as usize]))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ProcEvents {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+// Returns the netlink message which subscribes the process `pid` to the proc
+// connector's events.
+#[cfg(target_os = "linux")]
+fn listen_message(pid: Pid) -> Vec<u8> {
+    let op = PROC_CN_MCAST_LISTEN.to_ne_bytes();
+    let len = NLMSG_HDRLEN + CN_MSG_HDRLEN + op.len();
+    let mut msg = Vec::with_capacity(len);
+    // The netlink message header: its length, type (`NLMSG_DONE`), flags,
+    // sequence number, and sender.
+    msg.extend_from_slice(&(len as u32).to_ne_bytes());
+    msg.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+    msg.extend_from_slice(&0u16.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&pid.to_ne_bytes());
+    // The connector message header: the connector's ids, sequence number,
+    // acknowledgement number, payload length, and flags.
+    msg.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+    msg.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(&(op.len() as u16).to_ne_bytes());
+    msg.extend_from_slice(&0u16.to_ne_bytes());
+    msg.extend_from_slice(&op);
+    msg
+}
+
+// Reads the native endian `u32` at `offset` in `buf`.
+#[cfg(target_os = "linux")]
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// Parses the process events out of the netlink messages in `buf`, skipping
+// the events that the ps thread doesn't act on (and any that are cut short).
+#[cfg(target_os = "linux")]
+fn parse_proc_events(mut buf: &[u8]) -> Vec<ProcEvent> {
+    let mut events = vec![];
+    while let Some(len) = read_u32(buf, 0) {
+        let len = len as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        let event = &buf[NLMSG_HDRLEN..len];
+        if let Some(event) = event.get(CN_MSG_HDRLEN..).and_then(parse_proc_event) {
+            events.push(event);
+        }
+        // Netlink messages are padded to a multiple of 4 bytes.
+        buf = buf.get((len + 3) & !3..).unwrap_or(&[]);
+    }
+    events
+}
+
+// Parses a `struct proc_event`: the event's type, cpu, and timestamp,
+// followed by its data.
+#[cfg(target_os = "linux")]
+fn parse_proc_event(event: &[u8]) -> Option<ProcEvent> {
+    match read_u32(event, 0)? {
+        PROC_EVENT_NONE => Some(ProcEvent::Ack { err: read_u32(event, 16)? }),
+        PROC_EVENT_FORK => {
+            let parent = read_u32(event, 20)?;
+            let child_tid = read_u32(event, 24)?;
+            let child = read_u32(event, 28)?;
+            // A new thread rather than a new process.
+            if child_tid != child {
+                return None;
+            }
+            Some(ProcEvent::Fork { parent, child })
+        }
+        PROC_EVENT_EXEC => Some(ProcEvent::Exec(read_u32(event, 20)?)),
+        _ => None,
+    }
+}
+
+// Waits for the monitored process to be started, i.e. for its pid to be set.
+// Returns `None` if `done` is set before then.
+fn wait_for_cargo_test_pid(
+    cargo_test_pid: &Mutex<Option<Pid>>,
+    done: &AtomicBool,
+) -> Option<Pid> {
+    while !done.load(Ordering::Acquire) {
+        if let Some(pid) = *cargo_test_pid.lock().unwrap() {
+            return Some(pid);
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    None
+}
+
+// The values shared between the worker threads and the thread running the
+// monitored process. Each thread gets its own clone.
+#[derive(Clone, Debug, Default)]
+pub struct Shared {
+    // The monitored processes' pid, set once it has been started.
+    pub cargo_test_pid: Arc<Mutex<Option<Pid>>>,
+    // The monitored processes' descendants, as found by the last `run_ps`.
+    pub child_pids: Arc<Mutex<Vec<ProcKey>>>,
+    pub db: Arc<Mutex<Database>>,
+    // Set to stop the worker threads. It is stored with `Release` and loaded
+    // with `Acquire`, so that a worker which sees it set also sees everything
+    // done before it was set.
+    pub done: Arc<AtomicBool>,
+    // Set by the ps thread (along with `done`) once the `Config::stop_after`
+    // process has exited, so that the monitored process can be stopped.
+    pub stopped: Arc<AtomicBool>,
+    // The number of "status" files (of processes and threads) read by the
+    // measurements thread, e.g. to gauge its overhead. It is only a counter,
+    // and is read once the worker threads have been joined (which orders
+    // their writes before the read), so it can use `Relaxed`.
+    pub n_reads: Arc<AtomicU64>,
+}
+
+impl Shared {
+    pub fn new() -> Self {
+        Shared::default()
+    }
+
+    // Returns the measurements database. This must only be called once the
+    // worker threads have exited, so that we hold the only reference to it.
+    pub fn into_db(self) -> Database {
+        let db = Arc::try_unwrap(self.db)
+            .unwrap_or_else(|_| panic!("the worker threads are still running"));
+        db.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Launches a thread that continuously calls `run_ps`, updates the shared
+// `child_pids` vector, and inserts the child processes' pids and names
+// into the measurements database, as often as set by `config`. If
+// `include_root` is set, the monitored process itself is measured along with
+// its descendants. The child processes that have left the tree since the
+// previous sweep are measured one last time, in case they left at their peak.
+pub fn launch_ps_thread(
+    shared: Shared,
+    config: Config,
+    include_root: bool,
+    ignore: Vec<String>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let Shared { cargo_test_pid, child_pids, db, done, stopped, n_reads } = shared;
+        let cargo_test_pid = match wait_for_cargo_test_pid(&cargo_test_pid, &done) {
+            Some(pid) => pid,
+            None => return,
+        };
+        let start = Instant::now();
+        let interval = config.discovery_interval;
+        let fast_interval = Duration::from_millis(FAST_PS_INTERVAL_MS).min(interval);
+        // The sweeps that found the same processes as the previous sweep, and
+        // the processes that were already recorded as ignored, aren't logged.
+        let mut logged_pids = vec![];
+        let mut prev_pids: Vec<ProcKey> = vec![];
+        let mut buf = vec![];
+        // The processes found whose name contains `config.stop_after`.
+        let mut stop_after = BTreeSet::new();
+        #[cfg(target_os = "linux")]
+        let mut events = if config.proc_events {
+            match ProcEvents::open() {
+                Ok(events) => Some(events),
+                Err(e) => {
+                    eprintln!(
+                        "warning: can't listen to the proc connector's process events \
+                         ({}); polling for new child processes instead",
+                        e,
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        #[cfg(not(target_os = "linux"))]
+        if config.proc_events {
+            eprintln!(
+                "warning: process events are only supported on Linux; polling for new \
+                 child processes instead"
+            );
+        }
+        while !done.load(Ordering::Acquire) {
+            let (ps, ignored) =
+                find_descendants(&config.procfs, cargo_test_pid, include_root, &ignore);
+            let pids: Vec<ProcKey> = ps.iter().map(ProcEntry::key).collect();
+            // A process may reach its peak and leave the tree between two
+            // measurements, so the processes that have disappeared since the
+            // last sweep are measured one last time. Most have been reaped, so
+            // their "status" file is gone, but this catches the processes that
+            // were reparented out of the tree and are still running.
+            let exited: Vec<ProcKey> =
+                prev_pids.iter().filter(|key| !pids.contains(key)).copied().collect();
+            let mut final_statuses = vec![];
+            for key in &exited {
+                let (procfs, field) = (&config.procfs, &config.field);
+                if let Some(status) = read_final_status(procfs, *key, &mut buf, field) {
+                    final_statuses.push((*key, status));
+                }
+            }
+            n_reads.fetch_add(exited.len() as u64, Ordering::Relaxed);
+            prev_pids.clone_from(&pids);
+            if exited.iter().any(|key| stop_after.contains(key)) {
+                log!(LogLevel::Verbose, "the `--stop-after` process exited; stopping");
+                stopped.store(true, Ordering::Release);
+                done.store(true, Ordering::Release);
+            }
+            if let Some(name) = &config.stop_after {
+                for entry in &ps {
+                    if entry.pname.contains(name.as_str()) {
+                        stop_after.insert(entry.key());
+                    }
+                }
+            }
+            if pids != logged_pids {
+                let found: Vec<Pid> = pids.iter().map(|key| key.pid).collect();
+                log!(LogLevel::Verbose, "discovery sweep found pids {:?}", found);
+                logged_pids.clone_from(&pids);
+            }
+            *child_pids.lock().unwrap() = pids;
+            let mut db = db.lock().unwrap();
+            for entry in ignored {
+                if let Entry::Vacant(vacant) = db.ignored.entry(entry.key()) {
+                    log!(
+                        LogLevel::Verbose,
+                        "ignoring {} (pid {}) and its descendants",
+                        entry.pname,
+                        entry.pid,
+                    );
+                    vacant.insert(entry.pname);
+                }
+            }
+            for (key, status) in &final_statuses {
+                log!(
+                    LogLevel::Verbose,
+                    "final read of exited pid {}: {} kb",
+                    key.pid,
+                    status.vmlck,
+                );
+                db.update(*key, status);
+            }
+            for entry in ps {
+                match db.pinfos.get_mut(&entry.key()) {
+                    // When polling quickly we may find a process between it
+                    // forking and calling `exec`, so it is renamed once it
+                    // has `exec`ed.
+                    Some(pinfo) if pinfo.pname != entry.pname => {
+                        pinfo.cmdline = entry.cmdline;
+                        pinfo.pname = entry.pname;
+                    }
+                    Some(_) => {}
+                    None => {
+                        log!(
+                            LogLevel::Verbose,
+                            "new child process (pid {}): {}",
+                            entry.pid,
+                            entry.cmdline,
+                        );
+                        db.new_child_process(entry.key(), entry.pname, entry.cmdline);
+                    }
+                }
+            }
+            // Don't hold the database while sleeping, which would block the
+            // measurements thread.
+            drop(db);
+            // Search again as soon as a process we know of forks or `exec`s,
+            // which makes fast polling unnecessary.
+            #[cfg(target_os = "linux")]
+            if let Some(events) = events.as_mut() {
+                let known = |pid: Pid| {
+                    let is_child = prev_pids.iter().any(|key| key.pid == pid);
+                    pid == cargo_test_pid || is_child
+                };
+                events.wait(interval, |event| match *event {
+                    ProcEvent::Fork { parent, .. } => known(parent),
+                    ProcEvent::Exec(pid) => known(pid),
+                    ProcEvent::Ack { .. } => false,
+                });
+                continue;
+            }
+            if start.elapsed() < config.fast_discovery_window {
+                thread::sleep(fast_interval);
+            } else {
+                thread::sleep(interval);
+            }
+        }
+    })
+}
+
+// Launches a thread that continuously (every `config.measurement_interval`)
+// reads each child processes' "status" file, parses each file to get the
+// ammount memory locked by that child process, then updates the database with
+// the locked memory information. If `timeseries` is set, every measurement is
+// also recorded as a time series sample. If a `stream` is given, every
+// measurement is also written to it as a line of JSON as soon as it is taken.
+// If `per_thread` is set, the locked memory of each of the child processes'
+// threads is also measured. If a `warn_kbs` threshold is given, a warning is
+// printed (once per process) as soon as a child process locks at least that
+// many kbs. If an `on_sample` callback is given, it is called with each
+// measurement's pid, process name, and kbs locked. The thread returns the
+// `stream`, e.g. so that it can be reused for another run.
+pub fn launch_measurements_thread(
+    shared: Shared,
+    config: Config,
+    timeseries: bool,
+    per_thread: bool,
+    mut stream: Option<Box<dyn Write + Send>>,
+    warn_kbs: Option<u64>,
+    mut on_sample: Option<OnSample>,
+) -> JoinHandle<Option<Box<dyn Write + Send>>> {
+    thread::spawn(move || {
+        let Shared { cargo_test_pid, child_pids, db, done, n_reads, .. } = shared;
+        if wait_for_cargo_test_pid(&cargo_test_pid, &done).is_none() {
+            return stream;
+        }
+        let start = Instant::now();
+        // The buffers are reused across sweeps so that taking a sample costs
+        // a single open and read of each child process' "status" file.
+        let mut buf = vec![];
+        let mut sweep = vec![];
+        let mut thread_sweep = vec![];
+        let mut lines = String::new();
+        let mut samples = vec![];
+        let mut pids = vec![];
+        let mut warned = BTreeSet::new();
+        let mut denied = BTreeSet::new();
+        let mut failed = BTreeSet::new();
+        // The processes that have exited but which the ps thread hasn't
+        // noticed yet.
+        let mut gone = BTreeSet::new();
+        let mut prev_sweep_start = None;
+        while !done.load(Ordering::Acquire) {
+            let sweep_start = Instant::now();
+            sweep.clear();
+            thread_sweep.clear();
+            // Copy the child pids rather than holding the lock while reading
+            // the "status" files, which would block the ps thread.
+            pids.clone_from(&child_pids.lock().unwrap());
+            let mut sweep_reads = 0;
+            for child_pid in &pids {
+                if gone.contains(child_pid) {
+                    continue;
+                }
+                sweep_reads += 1;
+                let (procfs, field) = (&config.procfs, &config.field);
+                match read_status_retrying(procfs, child_pid.pid, &mut buf, field) {
+                    Ok(status) => sweep.push((*child_pid, status)),
+                    // The process has exited since it was found, so there is
+                    // nothing left to measure.
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                        gone.insert(*child_pid);
+                        continue;
+                    }
+                    // Otherwise the process would silently report 0 kb
+                    // locked, so we say why (once per process).
+                    Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                        if denied.insert(*child_pid) {
+                            eprintln!(
+                                "\nwarning: permission denied reading the locked memory \
+                                 of pid {}; try running with elevated privileges",
+                                child_pid.pid,
+                            );
+                        }
+                    }
+                    Err(e) => {
+                        if failed.insert(*child_pid) {
+                            log!(
+                                LogLevel::Verbose,
+                                "failed to read the status of pid {}: {}",
+                                child_pid.pid,
+                                e,
+                            );
+                        }
+                    }
+                }
+                if per_thread {
+                    let pid = child_pid.pid;
+                    let statuses = read_thread_statuses(procfs, pid, &mut buf, field);
+                    for (tid, status) in statuses {
+                        thread_sweep.push((*child_pid, tid, status.vmlck));
+                        sweep_reads += 1;
+                    }
+                }
+            }
+            n_reads.fetch_add(sweep_reads, Ordering::Relaxed);
+            // Update the database once per sweep rather than once per child
+            // process.
+            let elapsed = start.elapsed();
+            let mut db = db.lock().unwrap();
+            let mut total_kbs_locked = 0;
+            for (child_pid, status) in &sweep {
+                db.update_or_insert(&config.procfs, *child_pid, status);
+                if timeseries {
+                    db.record_sample(*child_pid, elapsed, status.vmlck);
+                }
+                if let (Some(_), Some(pinfo)) = (&stream, db.pinfos.get(child_pid)) {
+                    let sample = json!({
+                        "ts_ms": elapsed.as_millis() as u64,
+                        "pid": child_pid.pid,
+                        "pname": pinfo.pname,
+                        "vmlck_kb": status.vmlck,
+                    });
+                    lines.push_str(&sample.to_string());
+                    lines.push('\n');
+                }
+                if let (Some(_), Some(pinfo)) = (&on_sample, db.pinfos.get(child_pid)) {
+                    samples.push((child_pid.pid, pinfo.pname.clone(), status.vmlck));
+                }
+                let over_threshold =
+                    status.vmlck > 0 && warn_kbs.is_some_and(|kbs| status.vmlck >= kbs);
+                if over_threshold && warned.insert(*child_pid) {
+                    if let Some(pinfo) = db.pinfos.get(child_pid) {
+                        eprintln!(
+                            "\nwarning: {} (pid {}) has locked {} kb, close to the \
+                             soft locked memory limit",
+                            pinfo.pname, child_pid.pid, status.vmlck,
+                        );
+                    }
+                }
+                total_kbs_locked += status.vmlck;
+            }
+            for (child_pid, tid, kbs_locked) in &thread_sweep {
+                db.update_thread(*child_pid, *tid, *kbs_locked);
+            }
+            db.update_total(total_kbs_locked);
+            if let Some(prev_sweep_start) = prev_sweep_start {
+                db.record_sweep_interval(sweep_start - prev_sweep_start);
+            }
+            prev_sweep_start = Some(sweep_start);
+            drop(db);
+            // The samples are written once the database is unlocked, so that
+            // a slow reader doesn't hold up the other threads. We stop
+            // streaming if the reader goes away.
+            if let Some(writer) = stream.as_mut() {
+                let res = writer.write_all(lines.as_bytes()).and_then(|_| writer.flush());
+                if res.is_err() {
+                    stream = None;
+                }
+                lines.clear();
+            }
+            if let Some(on_sample) = on_sample.as_mut() {
+                for (pid, pname, kbs_locked) in samples.drain(..) {
+                    on_sample(pid, &pname, kbs_locked);
+                }
+            }
+            thread::sleep(config.measurement_interval);
+        }
+        stream
+    })
+}
+
+// A callback which is given each measurement's pid, process name, and kbs
+// locked.
+pub type OnSample = Box<dyn FnMut(Pid, &str, u64) + Send>;
+
+// Monitors the locked memory of `child_pid`'s descendants until `done` is
+// set, then returns the measurements. This uses the same defaults as
+// `cargo mlocktest`.
+pub fn monitor(child_pid: Pid, done: Arc<AtomicBool>) -> Database {
+    monitor_with(child_pid, done, |_, _, _| {})
+}
+
+// Like `monitor`, but also calls `on_sample` with each measurement's pid,
+// process name, and kbs locked as soon as it is taken, e.g. to check an
+// invariant while the tests run. If `on_sample` panics, monitoring stops and
+// the panic is resumed on the calling thread.
+pub fn monitor_with<F>(child_pid: Pid, done: Arc<AtomicBool>, on_sample: F) -> Database
+where
+    F: FnMut(Pid, &str, u64) + Send + 'static,
+{
+    let shared = Shared {
+        cargo_test_pid: Arc::new(Mutex::new(Some(child_pid))),
+        done,
+        ..Shared::new()
+    };
+    let ignore = IGNORE_CHILD_PROCS.iter().map(|pname| pname.to_string()).collect();
+    let ps_thread = launch_ps_thread(shared.clone(), Config::default(), false, ignore);
+    let measurements_thread = launch_measurements_thread(
+        shared.clone(),
+        Config::default(),
+        false,
+        false,
+        None,
+        None,
+        Some(Box::new(on_sample)),
+    );
+    let res = measurements_thread.join();
+    if res.is_err() {
+        shared.done.store(true, Ordering::Release);
+    }
+    let _ = ps_thread.join();
+    if let Err(e) = res {
+        panic::resume_unwind(e);
+    }
+    shared.into_db()
+}
+
+// The memory usage fields (in kbs) read from a processes' "status" file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Status {
+    pub vmlck: u64,
+    pub vmrss: u64,
+    pub vmhwm: u64,
+}
+
+// Parses an unsigned decimal integer from its ASCII digits, without first
+// converting them into a `str`.
+fn parse_u64(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |n, digit| {
+        if digit.is_ascii_digit() {
+            n.checked_mul(10)?.checked_add(u64::from(digit - b'0'))
+        } else {
+            None
+        }
+    })
+}
+
+// Parses the value of a "status" file field that is measured in kbs, e.g.
+// "VmLck:\t     8 kB". The unit is checked so that we never misinterpret a
+// value that the kernel reports in some other unit.
+fn parse_kb_field(line: &[u8]) -> Option<u64> {
+    let mut tokens = line
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|token| !token.is_empty())
+        .skip(1);
+    let value = parse_u64(tokens.next()?)?;
+    match tokens.next() {
+        Some(b"kB") => Some(value),
+        _ => None,
+    }
+}
+
+// Reads a processes' "status" file; parsing it for the ammount of memory
+// currently locked by the process along with its resident set size.
+pub fn parse_status_file(pid: Pid) -> Option<Status> {
+    parse_status_file_at(DEFAULT_PROCFS, pid)
+}
+
+// Like `parse_status_file`, but reads the "status" file from the proc
+// filesystem mounted at `procfs`.
+pub fn parse_status_file_at(procfs: &str, pid: Pid) -> Option<Status> {
+    read_status(procfs, pid, &mut vec![], DEFAULT_FIELD).ok()
+}
+
+// Like `parse_status_file`, but reads the "status" file into `buf` so that
+// the caller can reuse its allocation between reads. The file is parsed as
+// bytes, which saves validating that it is UTF-8 on every sample. Returns the
+// error so that the caller can tell a process that has exited (`NotFound`)
+// from one we aren't allowed to read (`PermissionDenied`).
+fn read_status(
+    procfs: &str,
+    pid: Pid,
+    buf: &mut Vec<u8>,
+    field: &str,
+) -> io::Result<Status> {
+    read_status_at(&format!("{}/{}/status", procfs, pid), buf, field)
+}
+
+// How long to wait before retrying a read of a "status" file that failed with
+// a transient error.
+const STATUS_RETRY_DELAY: Duration = Duration::from_micros(200);
+
+// Reads the "status" file of a process which has left the tree, or returns
+// `None` if it has exited. The status is only returned if the pid hasn't been
+// reused by another process, i.e. if its start time still matches `key`'s.
+fn read_final_status(
+    procfs: &str,
+    key: ProcKey,
+    buf: &mut Vec<u8>,
+    field: &str,
+) -> Option<Status> {
+    let status = read_status(procfs, key.pid, buf, field).ok()?;
+    let (_ppid, starttime) = read_stat(procfs, key.pid)?;
+    if starttime == key.starttime {
+        Some(status)
+    } else {
+        None
+    }
+}
+
+// Like `read_status`, but retries once, after a short pause, if the read fails
+// with a transient error, so that a busy process that is still running isn't
+// missing a sample.
+fn read_status_retrying(
+    procfs: &str,
+    pid: Pid,
+    buf: &mut Vec<u8>,
+    field: &str,
+) -> io::Result<Status> {
+    match read_status(procfs, pid, buf, field) {
+        Err(ref e) if is_transient(e) => {
+            thread::sleep(STATUS_RETRY_DELAY);
+            read_status(procfs, pid, buf, field)
+        }
+        result => result,
+    }
+}
+
+// Returns whether a failed read of a "status" file may succeed if retried:
+// it was interrupted (`EINTR`, `EAGAIN`) or the process was briefly
+// unreachable (`ESRCH`). `NotFound` means that the process is gone.
+fn is_transient(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(errno) => [libc::EINTR, libc::EAGAIN, libc::ESRCH].contains(&errno),
+        None => e.kind() == io::ErrorKind::Interrupted,
+    }
+}
+
+fn read_status_at(path: &str, buf: &mut Vec<u8>, field: &str) -> io::Result<Status> {
+    buf.clear();
+    fs::File::open(path)?.read_to_end(buf)?;
+    parse_status(buf, field).ok_or_else(|| {
+        let e = format!("`{}` has no `{}`", path, field);
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    })
+}
+
+// Reads the "status" file of each of a processes' threads. Returns an empty
+// vector if the processes' threads can't be listed.
+fn read_thread_statuses(
+    procfs: &str,
+    pid: Pid,
+    buf: &mut Vec<u8>,
+    field: &str,
+) -> Vec<(Pid, Status)> {
+    let entries = match fs::read_dir(format!("{}/{}/task", procfs, pid)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut statuses = vec![];
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let tid: Pid = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(tid) => tid,
+            None => continue,
+        };
+        let path = format!("{}/{}/task/{}/status", procfs, pid, tid);
+        if let Ok(status) = read_status_at(&path, buf, field) {
+            statuses.push((tid, status));
+        }
+    }
+    statuses
+}
+
+// Parses the memory usage fields out of the contents of a "status" file, with
+// `field` (usually `VmLck`) recorded as the locked memory. Every field is read
+// in a single pass over the whole file, so the order of its lines doesn't
+// matter. Returns `None` if the file doesn't contain `field` or if any of the
+// fields are malformed.
+fn parse_status(file: &[u8], field: &str) -> Option<Status> {
+    let mut status = Status::default();
+    let mut found_vmlck = false;
+    for line in file.split(|byte| *byte == b'\n') {
+        let name = match line.iter().position(|byte| *byte == b':') {
+            Some(colon) => &line[..colon],
+            None => continue,
+        };
+        if name == field.as_bytes() {
+            found_vmlck = true;
+            status.vmlck = parse_kb_field(line)?;
+        }
+        if name == b"VmRSS" {
+            status.vmrss = parse_kb_field(line)?;
+        } else if name == b"VmHWM" {
+            status.vmhwm = parse_kb_field(line)?;
+        }
+    }
+    if found_vmlck {
+        Some(status)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::process::{self, Command, Stdio};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{
+        format_kbs, is_transient, monitor, monitor_with, parse_cap_ipc_lock, parse_cpu_ticks,
+        parse_stat, parse_status, parse_status_file_at, read_cmdline, read_final_status,
+        read_thread_statuses, run_ps_at, Change, Color, Database, Limit, ProcEntry, ProcKey,
+        Renderer, Sort, Status, TableOptions, TableStyle, Verdict, DEFAULT_FIELD, DEFAULT_PROCFS,
+        DEFAULT_TABLE_STYLE, LIMIT_WARN_PERCENT,
+    };
+    #[cfg(target_os = "linux")]
+    use super::{
+        listen_message, parse_proc_events, ProcEvent, CN_MSG_HDRLEN, NLMSG_HDRLEN,
+        PROC_EVENT_EXEC, PROC_EVENT_FORK, PROC_EVENT_NONE,
+    };
+
+    // The number of kbs that `mlock_fixture` locks.
+    const FIXTURE_LOCKED_KB: u64 = 16;
+
+    const RAW_BY_PID: TableOptions = TableOptions {
+        sort: Sort::Pid,
+        field: DEFAULT_FIELD,
+        raw_kb: true,
+        stats: false,
+        show_rss: false,
+        show_samples: false,
+        show_released: false,
+        hide_zero: false,
+        full_cmd: false,
+        name_width: None,
+        per_thread: false,
+        top: None,
+        color: false,
+        warn_percent: LIMIT_WARN_PERCENT,
+        group_by_name: false,
+        renderer: Renderer::Ascii,
+        style: DEFAULT_TABLE_STYLE,
+    };
+
+    fn add_process(db: &mut Database, pid: u32, pname: &str, vmlck: u64) {
+        let key = ProcKey { pid, starttime: 0 };
+        db.new_child_process(key, pname.to_string(), pname.to_string());
+        db.update(key, &Status { vmlck, vmrss: 0, vmhwm: 0 });
+    }
+
+    // Not a test itself: `monitor_fixture` runs it in a child process, where it
+    // locks `FIXTURE_LOCKED_KB` of memory for long enough to be measured.
+    #[test]
+    #[ignore]
+    fn mlock_fixture() {
+        if std::env::var_os("MLOCKTEST_FIXTURE").is_none() {
+            return;
+        }
+        let buf = vec![0u8; FIXTURE_LOCKED_KB as usize * 1024];
+        let res = unsafe { libc::mlock(buf.as_ptr() as *const libc::c_void, buf.len()) };
+        assert_eq!(res, 0, "mlock failed: {}", std::io::Error::last_os_error());
+        thread::sleep(Duration::from_millis(500));
+    }
+
+    // Runs `mlock_fixture` in a child process, and `run_monitor` (given the
+    // fixture's pid and a flag that is set once the fixture has exited) on its
+    // own thread. Returns the fixture's pid and what `run_monitor` returned.
+    fn monitor_fixture<F, T>(run_monitor: F) -> (u32, T)
+    where
+        F: FnOnce(u32, Arc<AtomicBool>) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // Both this process and the fixture's are running this test binary.
+        let mut fixture = Command::new(std::env::current_exe().unwrap())
+            .args(["tests::mlock_fixture", "--exact", "--ignored", "--quiet"])
+            .env("MLOCKTEST_FIXTURE", "1")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let fixture_pid = fixture.id();
+        let done = Arc::new(AtomicBool::new(false));
+        let monitor_done = done.clone();
+        let monitor = thread::spawn(move || run_monitor(fixture_pid, monitor_done));
+        assert!(fixture.wait().unwrap().success());
+        done.store(true, Ordering::Release);
+        (fixture_pid, monitor.join().unwrap())
+    }
+
+    #[test]
+    fn test_monitor() {
+        let (fixture_pid, db) = monitor_fixture(|_, done| monitor(std::process::id(), done));
+        let key = db.pinfos.keys().find(|key| key.pid == fixture_pid).unwrap();
+        assert!(db.pinfos[key].max_locked >= FIXTURE_LOCKED_KB);
+        assert!(db.max_total_locked >= FIXTURE_LOCKED_KB);
+    }
+
+    #[test]
+    fn test_monitor_with() {
+        let max_locked = Arc::new(AtomicU64::new(0));
+        let on_sample_max = max_locked.clone();
+        monitor_fixture(move |fixture_pid, done| {
+            monitor_with(std::process::id(), done, move |pid, _pname, kbs| {
+                if pid == fixture_pid {
+                    on_sample_max.fetch_max(kbs, Ordering::Relaxed);
+                }
+            })
+        });
+        assert!(max_locked.load(Ordering::Relaxed) >= FIXTURE_LOCKED_KB);
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let fields = "42 1234 1234 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 5555 1000";
+        let stat = format!("1234 (cargo) S {}", fields);
+        assert_eq!(parse_stat(&stat), Some((42, 5555)));
+        let stat = format!("1234 (my (weird) name) R {}", fields);
+        assert_eq!(parse_stat(&stat), Some((42, 5555)));
+        // The contents of a "stat" file that was cut short, or is empty,
+        // because its process exited while the file was being read.
+        assert_eq!(parse_stat("1234 (cargo) S 42 1234 1234"), None);
+        assert_eq!(parse_stat("1234 (car"), None);
+        assert_eq!(parse_stat(""), None);
+        // The user and system CPU times are the 12th and 13th fields after
+        // the state.
+        let stat = format!("1234 (my (weird) name) R {}", fields);
+        assert_eq!(parse_cpu_ticks(&stat), Some(3));
+        assert_eq!(parse_cpu_ticks("1234 (cargo) S 42 1234 1234"), None);
+    }
+
+    #[test]
+    fn test_parse_cap_ipc_lock() {
+        let status = "Name:\tcargo\nCapEff:\t000001ffffffffff\nCapBnd:\t0\n";
+        assert_eq!(parse_cap_ipc_lock(status), Some(true));
+        let status = "CapPrm:\t0000000000004000\nCapEff:\t0000000000000000\n";
+        assert_eq!(parse_cap_ipc_lock(status), Some(false));
+        assert_eq!(parse_cap_ipc_lock("CapEff:\t0000000000004000\n"), Some(true));
+        assert_eq!(parse_cap_ipc_lock("CapEff:\tzz\n"), None);
+        assert_eq!(parse_cap_ipc_lock("Name:\tcargo\n"), None);
+    }
+
+    #[test]
+    fn test_procfs_fixture() {
+        // A fake proc filesystem: `cargo` (pid 100) runs a test binary (101),
+        // which runs `rustc` (102, ignored), which runs 103.
+        let procfs = env::temp_dir().join(format!("mlocktest-procfs-{}", process::id()));
+        let fields = "0 0 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 5555 1000";
+        let processes = [
+            (100, 1, "cargo\0test\0"),
+            (101, 100, "/target/debug/deps/test_bin\0--quiet\0"),
+            (102, 101, "rustc\0"),
+            (103, 102, "cc\0"),
+        ];
+        for (pid, ppid, cmdline) in &processes {
+            let dir = procfs.join(pid.to_string());
+            fs::create_dir_all(&dir).unwrap();
+            let stat = format!("{} (comm) S {} {}", pid, ppid, fields);
+            fs::write(dir.join("stat"), stat).unwrap();
+            fs::write(dir.join("cmdline"), cmdline).unwrap();
+        }
+        let status = "Name:\ttest_bin\nVmLck:\t      32 kB\nVmRSS:\t    1024 kB\n";
+        fs::write(procfs.join("101").join("status"), status).unwrap();
+
+        let procfs = procfs.to_str().unwrap();
+        let ignore = vec!["rustc".to_string()];
+        let ps = run_ps_at(procfs, 100, false, &ignore);
+        let expected = ProcEntry {
+            pid: 101,
+            ppid: 100,
+            pname: "test_bin".to_string(),
+            cmdline: "/target/debug/deps/test_bin --quiet".to_string(),
+            starttime: 5555,
+        };
+        assert_eq!(ps, vec![expected]);
+        assert_eq!(ps[0].key(), ProcKey { pid: 101, starttime: 5555 });
+        let root = run_ps_at(procfs, 100, true, &ignore);
+        assert_eq!((root[0].pid, root[0].ppid, root.len()), (100, 1, 2));
+        let status = parse_status_file_at(procfs, 101);
+        assert_eq!(status, Some(Status { vmlck: 32, vmrss: 1024, vmhwm: 0 }));
+        assert_eq!(read_cmdline(procfs, 101, "test_bin"), "/target/debug/deps/test_bin --quiet");
+        assert_eq!(parse_status_file_at(procfs, 100), None);
+        let mut buf = vec![];
+        let key = ProcKey { pid: 101, starttime: 5555 };
+        assert!(read_final_status(procfs, key, &mut buf, DEFAULT_FIELD).is_some());
+        // Pid 101 has been reused by a process started later.
+        let key = ProcKey { pid: 101, starttime: 5000 };
+        assert!(read_final_status(procfs, key, &mut buf, DEFAULT_FIELD).is_none());
+        fs::remove_dir_all(procfs).unwrap();
+    }
+
+    #[test]
+    fn test_parse_status_file_fixture() {
+        let procfs = env::temp_dir().join(format!("mlocktest-status-{}", process::id()));
+        let files: [(&str, Option<u64>); 9] = [
+            ("Name:\tcargo\nVmLck:\t       8 kB\nVmRSS:\t    1024 kB\n", Some(8)),
+            ("Name:\tcargo\nVmRSS:\t    1024 kB\n", None),
+            ("VmLck:  \t  16   kB   \n", Some(16)),
+            ("VmLck:\t 4 kB", Some(4)),
+            ("VmLck:\t 4 kB\r\n", Some(4)),
+            ("VmLck:\n", None),
+            ("VmLck:\t kB\n", None),
+            ("VmLck:\t 4 4 kB\n", None),
+            ("", None),
+        ];
+        for (pid, (file, _)) in files.iter().enumerate() {
+            let dir = procfs.join(pid.to_string());
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("status"), file).unwrap();
+        }
+        let procfs = procfs.to_str().unwrap();
+        for (pid, (file, expected)) in files.iter().enumerate() {
+            let vmlck = parse_status_file_at(procfs, pid as u32).map(|status| status.vmlck);
+            assert_eq!(vmlck, *expected, "status file {:?}", file);
+        }
+        // A process without a "status" file, e.g. because it exited.
+        assert_eq!(parse_status_file_at(procfs, files.len() as u32), None);
+        fs::remove_dir_all(procfs).unwrap();
+    }
+
+    #[test]
+    fn test_format_kbs() {
+        assert_eq!(format_kbs(0), "0.0 KiB");
+        assert_eq!(format_kbs(64), "64.0 KiB");
+        assert_eq!(format_kbs(4608), "4.5 MiB");
+        assert_eq!(format_kbs(3 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(format_kbs(2048 * 1024 * 1024), "2048.0 GiB");
+    }
+
+    #[test]
+    fn test_parse_status_units() {
+        let file = b"VmHWM:\t    2048 kB\nVmRSS:\t    1024 kB\nVmLck:\t       8 kB\n";
+        let status = parse_status(file, DEFAULT_FIELD);
+        assert_eq!(status, Some(Status { vmlck: 8, vmrss: 1024, vmhwm: 2048 }));
+        assert_eq!(parse_status(b"VmLck:\t       8 MB\n", DEFAULT_FIELD), None);
+        assert_eq!(parse_status(b"VmLck:\t       8\n", DEFAULT_FIELD), None);
+        assert_eq!(parse_status(b"VmLck:\t     -8 kB\n", DEFAULT_FIELD), None);
+        assert_eq!(parse_status(b"VmLck:\t99999999999999999999 kB\n", DEFAULT_FIELD), None);
+        assert_eq!(parse_status(b"VmRSS:\t    1024 kB\n", DEFAULT_FIELD), None);
+        let status = parse_status(file, "VmRSS");
+        assert_eq!(status, Some(Status { vmlck: 1024, vmrss: 1024, vmhwm: 2048 }));
+        let status = parse_status(b"VmSwap:\t       4 kB\n", "VmSwap");
+        assert_eq!(status, Some(Status { vmlck: 4, vmrss: 0, vmhwm: 0 }));
+        assert_eq!(parse_status(b"VmLckd:\t       4 kB\n", DEFAULT_FIELD), None);
+    }
+
+    #[test]
+    fn test_limit_from_str() {
+        assert!(matches!("unlimited".parse(), Ok(Limit::Unlimited)));
+        assert!(matches!("0".parse(), Ok(Limit::Kb(0))));
+        assert!(matches!("65536".parse(), Ok(Limit::Kb(64))));
+        // Limits are given in bytes and are rounded down to whole kbs.
+        assert!(matches!("1023".parse(), Ok(Limit::Kb(0))));
+        assert!(matches!("2047".parse(), Ok(Limit::Kb(1))));
+        assert!(matches!(
+            "18446744073709551615".parse(),
+            Ok(Limit::Kb(kbs)) if kbs == u64::MAX / 1024
+        ));
+        assert!("18446744073709551616".parse::<Limit>().is_err());
+        assert!("64k".parse::<Limit>().is_err());
+        assert!("".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn test_limit_display() {
+        assert_eq!(Limit::Kb(64).to_string(), "64");
+        assert_eq!(format!("{:#}", Limit::Kb(64)), "64.0 KiB");
+        assert_eq!(format!("{:#}", Limit::Kb(16 * 1024)), "16.0 MiB");
+        assert_eq!(Limit::Unlimited.to_string(), "unlimited");
+        assert_eq!(format!("{:#}", Limit::Unlimited), "unlimited");
+    }
+
+    #[test]
+    fn test_verdict_check() {
+        assert_eq!(Verdict::check(0, &Limit::Kb(64)), Some(Verdict::Pass));
+        assert_eq!(Verdict::check(57, &Limit::Kb(64)), Some(Verdict::Pass));
+        assert_eq!(Verdict::check(58, &Limit::Kb(64)), Some(Verdict::Warn));
+        assert_eq!(Verdict::check(64, &Limit::Kb(64)), Some(Verdict::Fail));
+        assert_eq!(Verdict::check(0, &Limit::Kb(0)), Some(Verdict::Fail));
+        assert_eq!(Verdict::check(64, &Limit::Unlimited), None);
+        assert_eq!(Verdict::check_with(50, &Limit::Kb(100), 50), Some(Verdict::Warn));
+        assert_eq!(Verdict::check_with(49, &Limit::Kb(100), 50), Some(Verdict::Pass));
+        let huge = Limit::Kb(u64::MAX / 2);
+        assert_eq!(Verdict::check_with(1, &huge, 100), Some(Verdict::Pass));
+    }
+
+    #[test]
+    fn test_read_cmdline() {
+        let cmdline = read_cmdline(DEFAULT_PROCFS, std::process::id(), "unused");
+        let arg0 = std::env::args().next().unwrap();
+        assert!(cmdline.starts_with(&arg0));
+        assert_eq!(read_cmdline(DEFAULT_PROCFS, u32::MAX, "gone"), "gone");
+    }
+
+    #[test]
+    fn test_table_top() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 8);
+        add_process(&mut db, 2, "b", 64);
+        add_process(&mut db, 3, "c", 0);
+        add_process(&mut db, 4, "d", 16);
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit\n",
+            "============        ======================        ===============\n",
+            "b                   64                            64\n",
+            "d                   16                            16\n",
+            "=================================================================\n",
+            "... and 2 more",
+        );
+        let options = TableOptions { top: Some(2), ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+        let options = TableOptions { top: Some(4), ..RAW_BY_PID };
+        assert!(!db.table(&Limit::Kb(100), &options).contains("more"));
+        let options = TableOptions { hide_zero: true, ..RAW_BY_PID };
+        let table = db.table(&Limit::Kb(100), &options);
+        assert!(table.contains("\na ") && table.contains("\nd "));
+        assert!(!table.contains("\nc "));
+        // The percentage saturates rather than overflowing.
+        add_process(&mut db, 5, "e", u64::MAX);
+        let table = db.table(&Limit::Kb(u64::MAX / 2), &RAW_BY_PID);
+        assert!(table.contains(&format!("\ne                   {}          2\n", u64::MAX)));
+    }
+
+    #[test]
+    fn test_table_markdown() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a|b", 64);
+        let key = ProcKey { pid: 1, starttime: 0 };
+        db.update_thread(key, 7, 8);
+        let expected = concat!(
+            "| Process Name | Max Locked Memory (kb) | % of hard limit |\n",
+            "| --- | --- | --- |\n",
+            "| a\\|b | 64 | 64 |\n",
+            "| &nbsp;&nbsp;tid 7 | 8 | 8 |",
+        );
+        let options = TableOptions {
+            per_thread: true,
+            renderer: Renderer::Markdown,
+            ..RAW_BY_PID
+        };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+
+    #[test]
+    fn test_table_color() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 8);
+        add_process(&mut db, 2, "b", 95);
+        add_process(&mut db, 3, "c", 100);
+        let options = TableOptions { color: true, ..RAW_BY_PID };
+        let table = db.to_table(&Limit::Kb(100), &options);
+        assert_eq!(table.colors, vec![None, Some(Color::Yellow), Some(Color::Red)]);
+        let rendered = table.render(Renderer::Ascii);
+        assert!(rendered.contains("\n\x1b[31mc                   100"));
+        assert!(!table.render(Renderer::Markdown).contains('\x1b'));
+        let table = db.to_table(&Limit::Kb(100), &RAW_BY_PID);
+        assert_eq!(table.colors, vec![None, None, None]);
+    }
+
+    // Builds a proc connector netlink message carrying a `struct proc_event`
+    // of type `what` with the given data.
+    #[cfg(target_os = "linux")]
+    fn proc_event_message(what: u32, data: &[u32]) -> Vec<u8> {
+        let mut event = what.to_ne_bytes().to_vec();
+        // The cpu and timestamp.
+        event.resize(16, 0);
+        for n in data {
+            event.extend_from_slice(&n.to_ne_bytes());
+        }
+        let len = NLMSG_HDRLEN + CN_MSG_HDRLEN + event.len();
+        let mut msg = (len as u32).to_ne_bytes().to_vec();
+        msg.resize(NLMSG_HDRLEN + CN_MSG_HDRLEN, 0);
+        msg.extend_from_slice(&event);
+        msg
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_parse_proc_events() {
+        let mut buf = proc_event_message(PROC_EVENT_NONE, &[0]);
+        buf.extend(proc_event_message(PROC_EVENT_FORK, &[10, 10, 11, 11]));
+        // A new thread of process 10, which is skipped.
+        buf.extend(proc_event_message(PROC_EVENT_FORK, &[10, 10, 12, 10]));
+        buf.extend(proc_event_message(PROC_EVENT_EXEC, &[11, 11]));
+        // An event that we don't act on (an exit).
+        buf.extend(proc_event_message(0x80000000, &[11, 11, 0, 0]));
+        let expected = vec![
+            ProcEvent::Ack { err: 0 },
+            ProcEvent::Fork { parent: 10, child: 11 },
+            ProcEvent::Exec(11),
+        ];
+        assert_eq!(parse_proc_events(&buf), expected);
+        // A message that was cut short.
+        let msg = proc_event_message(PROC_EVENT_EXEC, &[11, 11]);
+        assert_eq!(parse_proc_events(&msg[..msg.len() - 1]), vec![]);
+        assert_eq!(listen_message(1).len(), NLMSG_HDRLEN + CN_MSG_HDRLEN + 4);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&io::Error::from_raw_os_error(libc::EINTR)));
+        assert!(is_transient(&io::Error::from_raw_os_error(libc::ESRCH)));
+        assert!(is_transient(&io::Error::from(io::ErrorKind::Interrupted)));
+        assert!(!is_transient(&io::Error::from_raw_os_error(libc::ENOENT)));
+        assert!(!is_transient(&io::Error::from_raw_os_error(libc::EACCES)));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::InvalidData)));
+    }
+
+    #[test]
+    fn test_read_thread_statuses() {
+        let pid = std::process::id();
+        let statuses = read_thread_statuses(DEFAULT_PROCFS, pid, &mut vec![], "VmLck");
+        assert!(!statuses.is_empty());
+        let gone = read_thread_statuses(DEFAULT_PROCFS, u32::MAX, &mut vec![], "VmLck");
+        assert!(gone.is_empty());
+    }
+
+    #[test]
+    fn test_table_per_thread() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 64);
+        let key = ProcKey { pid: 1, starttime: 0 };
+        db.update_thread(key, 1, 8);
+        db.update_thread(key, 2, 64);
+        db.update_thread(key, 2, 16);
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit\n",
+            "============        ======================        ===============\n",
+            "a                   64                            64\n",
+            "  tid 1             8                             8\n",
+            "  tid 2             64                            64\n",
+            "=================================================================",
+        );
+        let options = TableOptions { per_thread: true, ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+
+    #[test]
+    fn test_table_field() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 2048);
+        let expected = concat!(
+            "\n",
+            "Process Name        Max VmRSS (kb)\n",
+            "============        ==============\n",
+            "a                   2048\n",
+            "==================================",
+        );
+        let options = TableOptions { field: "VmRSS", ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+
+    #[test]
+    fn test_table_group_by_name() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 8);
+        add_process(&mut db, 2, "b", 16);
+        add_process(&mut db, 3, "a", 64);
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit        PIDs\n",
+            "============        ======================        ===============        ====\n",
+            "a                   64                            64                     2\n",
+            "b                   16                            16                     1\n",
+            "=============================================================================",
+        );
+        let options = TableOptions { group_by_name: true, ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+
+    #[test]
+    fn test_table_samples() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 8);
+        let key = ProcKey { pid: 1, starttime: 0 };
+        db.update(key, &Status { vmlck: 16, vmrss: 0, vmhwm: 0 });
+        let key = ProcKey { pid: 2, starttime: 0 };
+        db.new_child_process(key, "missed".to_string(), "missed".to_string());
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit        Samples\n",
+            "============        ======================        ===============        =======\n",
+            "a                   16                            16                     2\n",
+            "missed              0                             0                      0\n",
+            "================================================================================",
+        );
+        let options = TableOptions { show_samples: true, ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+
+    #[test]
+    fn test_table_name_width() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a_very_long_test_binary_name", 64);
+        add_process(&mut db, 2, "short", 8);
+        add_process(&mut db, 3, "a_very_long_test_binary_name_2", 8);
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit\n",
+            "============        ======================        ===============\n",
+            "a_very_lo…          64                            64\n",
+            "short               8                             8\n",
+            "a_very_lo…          8                             8\n",
+            "=================================================================",
+        );
+        let options = TableOptions { name_width: Some(10), ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+        // Names which only look the same once truncated aren't grouped.
+        let options = TableOptions { group_by_name: true, ..options };
+        assert_eq!(db.to_table(&Limit::Kb(100), &options).rows.len(), 3);
+    }
+
+    #[test]
+    fn test_table_released() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "freed", 8);
+        let key = ProcKey { pid: 1, starttime: 0 };
+        db.update(key, &Status { vmlck: 0, vmrss: 0, vmhwm: 0 });
+        add_process(&mut db, 2, "leaked", 8);
+        add_process(&mut db, 3, "unlocked", 0);
+        assert!(db.pinfos[&key].released);
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit        Released\n",
+            "============        ======================        ===============        ========\n",
+            "freed               8                             8                      yes\n",
+            "leaked              8                             8                      no\n",
+            "unlocked            0                             0                      n/a\n",
+            "=================================================================================",
+        );
+        let options = TableOptions { show_released: true, ..RAW_BY_PID };
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+
+    #[test]
+    fn test_update_or_insert() {
+        let mut db = Database::new();
+        let key = ProcKey { pid: std::process::id(), starttime: 0 };
+        let status = |vmlck| Status { vmlck, vmrss: 0, vmhwm: 0 };
+        db.update_or_insert(DEFAULT_PROCFS, key, &status(8));
+        let pinfo = &db.pinfos[&key];
+        assert!(!pinfo.pname.is_empty());
+        assert_eq!((pinfo.max_locked, pinfo.n_samples), (8, 1));
+        db.update_or_insert(DEFAULT_PROCFS, key, &status(16));
+        assert_eq!((db.pinfos[&key].max_locked, db.pinfos[&key].n_samples), (16, 2));
+    }
+
+    #[test]
+    fn test_update_stats() {
+        let mut db = Database::new();
+        let key = ProcKey { pid: 1, starttime: 0 };
+        db.new_child_process(key, "a".to_string(), "a".to_string());
+        assert_eq!(db.pinfos[&key].avg_locked(), 0);
+        for vmlck in &[8, 64, 0, 16] {
+            db.update(key, &Status { vmlck: *vmlck, vmrss: 0, vmhwm: 0 });
+        }
+        let pinfo = &db.pinfos[&key];
+        assert_eq!((pinfo.min_locked, pinfo.max_locked, pinfo.avg_locked()), (0, 64, 22));
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let mut db = Database::new();
+        add_process(&mut db, 2, "a, b", 64);
+        add_process(&mut db, 1, "test bin", 8);
+        add_process(&mut db, 3, "say \"hi\"", 0);
+        let expected = concat!(
+            "pid,pname,max_locked_kb\n",
+            "1,\"test bin\",8\n",
+            "2,\"a, b\",64\n",
+            "3,\"say \"\"hi\"\"\",0\n",
+        );
+        assert_eq!(db.to_csv(), expected);
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut baseline = Database::new();
+        add_process(&mut baseline, 1, "a", 8);
+        add_process(&mut baseline, 2, "b", 64);
+        add_process(&mut baseline, 3, "c", 16);
+        add_process(&mut baseline, 4, "d", 16);
+        let baseline = Database::from_json(&baseline.to_json()).unwrap();
+        let mut db = Database::new();
+        add_process(&mut db, 5, "a", 32);
+        add_process(&mut db, 6, "b", 0);
+        add_process(&mut db, 7, "c", 16);
+        add_process(&mut db, 8, "e", 8);
+        let changes: Vec<(String, Change, u64)> = db
+            .compare(&baseline)
+            .into_iter()
+            .map(|delta| (delta.pname.clone(), delta.change(), delta.increase()))
+            .collect();
+        let expected = vec![
+            ("a".to_string(), Change::Increased, 24),
+            ("b".to_string(), Change::Decreased, 0),
+            ("c".to_string(), Change::Unchanged, 0),
+            ("d".to_string(), Change::Removed, 0),
+            ("e".to_string(), Change::New, 0),
+        ];
+        assert_eq!(changes, expected);
+
+        // A pretty printed report can also be used as the baseline.
+        let pretty = Database::from_json(&baseline.to_json_pretty()).unwrap();
+        assert!(baseline.to_json_pretty().contains("\n  {\n"));
+        assert_eq!(pretty.to_json(), baseline.to_json());
+        assert!(Database::from_json("{}").is_err());
+        assert!(Database::from_json("[{\"pid\": 1}]").is_err());
+    }
+
+    #[test]
+    fn test_filtered() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "my_crate-1a2b", 8);
+        add_process(&mut db, 2, "other-3c4d", 64);
+        let key = ProcKey { pid: 3, starttime: 0 };
+        db.new_child_process(key, "sh".to_string(), "sh -c my_crate".to_string());
+        db.update_total(72);
+        let filtered = db.filtered("my_crate");
+        let pids: Vec<u32> = filtered.pinfos.keys().map(|key| key.pid).collect();
+        assert_eq!(pids, vec![1, 3]);
+        assert_eq!(filtered.max_total_locked, 72);
+    }
+
+    #[test]
+    fn test_sweep_resolution() {
+        let mut db = Database::new();
+        assert_eq!(db.sweep_resolution(), None);
+        for ms in &[1, 1, 2, 1, 15] {
+            db.record_sweep_interval(Duration::from_millis(*ms));
+        }
+        let expected = (Duration::from_millis(1), Duration::from_millis(15));
+        assert_eq!(db.sweep_resolution(), Some(expected));
+        let mut other = Database::new();
+        for _ in 0..4 {
+            other.record_sweep_interval(Duration::from_millis(2));
+        }
+        db.merge(other);
+        let expected = (Duration::from_millis(2), Duration::from_millis(15));
+        assert_eq!(db.sweep_resolution(), Some(expected));
+    }
+
+    #[test]
+    fn test_census() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "my_crate-1a2b", 8);
+        assert_eq!(db.census(1), "Observed 1 process, 1 reported");
+        for pid in 2..5 {
+            db.ignored.insert(ProcKey { pid, starttime: 0 }, "rustc".to_string());
+        }
+        db.ignored.insert(ProcKey { pid: 5, starttime: 0 }, "rustdoc".to_string());
+        assert_eq!(
+            db.census(0),
+            "Observed 5 processes (3 rustc, 1 rustdoc ignored), 0 reported",
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 8);
+        add_process(&mut db, 2, "b", 64);
+        db.update_total(72);
+        let mut other = Database::new();
+        add_process(&mut other, 1, "a", 16);
+        add_process(&mut other, 2, "b", 0);
+        add_process(&mut other, 3, "c", 32);
+        other.update_total(48);
+        db.merge(other);
+        let max_locked: Vec<(u32, u64, u64)> = db
+            .pinfos
+            .iter()
+            .map(|(key, pinfo)| (key.pid, pinfo.max_locked, pinfo.n_samples))
+            .collect();
+        assert_eq!(max_locked, vec![(1, 16, 2), (2, 64, 2), (3, 32, 1)]);
+        assert_eq!(db.max_total_locked, 72);
+    }
+
+    #[test]
+    fn test_merge_run() {
+        let mut db = Database::new();
+        add_process(&mut db, 10, "test_bin", 8);
+        add_process(&mut db, 11, "sh", 0);
+        add_process(&mut db, 12, "sh", 4);
+        // The next run's processes have other pids, and one more `sh`.
+        let mut other = Database::new();
+        add_process(&mut other, 20, "test_bin", 16);
+        add_process(&mut other, 21, "sh", 2);
+        add_process(&mut other, 22, "sh", 0);
+        add_process(&mut other, 23, "sh", 1);
+        db.merge_run(other);
+        let max_locked: Vec<(u32, &str, u64)> = db
+            .pinfos
+            .iter()
+            .map(|(key, pinfo)| (key.pid, pinfo.pname.as_str(), pinfo.max_locked))
+            .collect();
+        let expected = vec![(10, "test_bin", 16), (11, "sh", 2), (12, "sh", 4), (23, "sh", 1)];
+        assert_eq!(max_locked, expected);
+    }
+
+    #[test]
+    fn test_table_empty() {
+        let db = Database::new();
+        let expected = concat!(
+            "\n",
+            "Process Name        Max Locked Memory (kb)        % of hard limit\n",
+            "============        ======================        ===============\n",
+            "=================================================================",
+        );
+        assert_eq!(db.table(&Limit::Kb(100), &RAW_BY_PID), expected);
+    }
+
+    #[test]
+    fn test_table_alignment() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 0);
+        // Longer than the "Process Name" heading, which widens the column.
+        add_process(&mut db, 2, "a_very_long_test_binary_name", 64);
+        // Multibyte characters are counted as a single column each.
+        add_process(&mut db, 3, "tëst-ünïcode", 8);
+        let expected = concat!(
+            "\n",
+            "Process Name                        Max Locked Memory (kb)        % of hard limit\n",
+            "============                        ======================        ===============\n",
+            "a                                   0                             0\n",
+            "a_very_long_test_binary_name        64                            64\n",
+            "tëst-ünïcode                        8                             8\n",
+            "=================================================================================",
+        );
+        assert_eq!(db.table(&Limit::Kb(100), &RAW_BY_PID), expected);
+
+        let expected = concat!(
+            "\n",
+            "Process Name                        Max Locked Memory        % of hard limit\n",
+            "============                        =================        ===============\n",
+            "a_very_long_test_binary_name        64.0 KiB                 n/a\n",
+            "tëst-ünïcode                        8.0 KiB                  n/a\n",
+            "a                                   0.0 KiB                  n/a\n",
+            "============================================================================",
+        );
+        assert_eq!(db.table(&Limit::Unlimited, &TableOptions::default()), expected);
+    }
+
+    #[test]
+    fn test_table_style() {
+        let mut db = Database::new();
+        add_process(&mut db, 1, "a", 64);
+        let style = TableStyle { column_gap: 2, border: '-', borders: true };
+        let options = TableOptions { style, ..RAW_BY_PID };
+        let expected = concat!(
+            "\n",
+            "Process Name  Max Locked Memory (kb)  % of hard limit\n",
+            "------------  ----------------------  ---------------\n",
+            "a             64                      64\n",
+            "-----------------------------------------------------",
+        );
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+
+        let style = TableStyle { borders: false, ..style };
+        let options = TableOptions { style, ..RAW_BY_PID };
+        let expected = concat!(
+            "\n",
+            "Process Name  Max Locked Memory (kb)  % of hard limit\n",
+            "a             64                      64",
+        );
+        assert_eq!(db.table(&Limit::Kb(100), &options), expected);
+    }
+}
@@ -1,341 +1,2189 @@
+// We can only monitor locked memory on Linux, elsewhere `main` just reports
+// that the platform is unsupported and the rest of the binary goes unused.
+#![cfg_attr(not(target_os = "linux"), allow(dead_code, unused_imports, unused_macros))]
+
+extern crate cargo_mlocktest;
+extern crate libc;
 #[cfg(test)]
 extern crate memsec;
 
-use std::collections::BTreeMap;
 use std::env;
-use std::fmt::{self, Display, Formatter};
-use std::fs;
-use std::iter;
-use std::process::{Command, Stdio};
-use std::str::{self, FromStr};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use std::thread::{self, JoinHandle};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+#[cfg(target_os = "linux")]
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+use std::thread;
+
+#[cfg(target_os = "linux")]
+use cargo_mlocktest::run_prlimit;
+use cargo_mlocktest::{
+    format_kbs, has_cap_ipc_lock, launch_measurements_thread, launch_ps_thread,
+    parse_status_file, read_cpu_time, run_ps, set_log_level, LogLevel,
+    Change, Database, Delta, Limit, MlockLimit, Pid, Pinfo, Renderer, Shared, Sort, Table,
+    TableOptions, TableStyle, Verdict, Config, DEFAULT_FIELD, DEFAULT_PROCFS,
+    IGNORE_CHILD_PROCS, LIMIT_WARN_PERCENT,
+};
+
+// We warn when the soft locked memory limit is at or below this many kbs,
+// 64 kb being the default on many Linux systems.
+const DEFAULT_MIN_SOFT_LIMIT_KB: u64 = 64;
+
+// The default max number of runs with `--repeat-until-spike`.
+const DEFAULT_MAX_ITERS: u32 = 100;
+
+// The exit codes of `cargo mlocktest`, for CI scripts to branch on. When the
+// tests fail, their exit code is passed on instead, and when we are
+// interrupted by a signal, we exit with `EXIT_SIGNAL_BASE` plus the signal's
+// number.
+const EXIT_OK: i32 = 0;
+// `cargo test` was killed by a signal, so it has no exit code. This is only
+// used when we weren't interrupted ourselves, e.g. if the tests crashed.
+const EXIT_KILLED: i32 = 1;
+// A process exceeded `--max-locked-kb` or regressed from the `--baseline`.
+const EXIT_LIMIT_EXCEEDED: i32 = 2;
+// No child processes were observed while running with `--require-samples`.
+const EXIT_NO_SAMPLES: i32 = 4;
+// We couldn't monitor the tests, e.g. because of an invalid argument, a file
+// we couldn't read or write, or a locked memory limit we couldn't read.
+const EXIT_ERROR: i32 = 5;
+// `--timeout-secs` elapsed. This is the exit code used by `timeout`.
+const EXIT_TIMED_OUT: i32 = 124;
+// We were interrupted by a signal, whose number is added to this, as shells
+// do for a process killed by a signal.
+const EXIT_SIGNAL_BASE: i32 = 128;
+
+// The format used to output the measurements database.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Table,
+    Markdown,
+    Json,
+    // JSON indented for reading rather than on a single line.
+    JsonPretty,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
 
-// The number of space characters (" ") between table columns.
-const COLUMN_BUFFER: usize = 8;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "markdown" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            "json-pretty" => Ok(Format::JsonPretty),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("invalid `--format`: `{}`", s)),
+        }
+    }
+}
+
+impl Format {
+    // Returns whether the report is meant to be parsed rather than read.
+    fn is_machine_readable(self) -> bool {
+        matches!(self, Format::Json | Format::JsonPretty | Format::Csv)
+    }
+}
+
+// Set when a machine-readable report is written to stdout, in which case
+// everything else that we (and the monitored command) would print to stdout
+// goes to stderr instead, so that stdout can be parsed.
+static REPORT_ON_STDOUT: AtomicBool = AtomicBool::new(false);
+
+// Like `println!`, but prints to stderr when the report is on stdout.
+macro_rules! sayln {
+    ($($arg:tt)*) => {
+        if REPORT_ON_STDOUT.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Like `print!`, but prints to stderr when the report is on stdout.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if REPORT_ON_STDOUT.load(Ordering::Relaxed) {
+            eprint!($($arg)*);
+        } else {
+            print!($($arg)*);
+        }
+    };
+}
 
-// Ignore child processes with the following names.
-const IGNORE_CHILD_PROCS: [&str; 3] = ["rustc", "[rustc]", "rustdoc"];
+// Which locked memory limit the limit check compares against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Against {
+    // The limit the kernel enforces.
+    Soft,
+    // The ceiling a process may raise its soft limit to at runtime.
+    Hard,
+}
 
-type Pid = u32;
-type Pname = String;
+impl FromStr for Against {
+    type Err = String;
 
-#[derive(Clone, Debug)]
-struct Pinfo {
-    pname: Pname,
-    max_locked: u64,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "soft" => Ok(Against::Soft),
+            "hard" => Ok(Against::Hard),
+            _ => Err(format!("invalid `--against`: `{}`", s)),
+        }
+    }
 }
 
+// The command line options for `cargo mlocktest`. Any arguments that are not
+// options for `cargo mlocktest` are forwarded to `cargo test`, unless a
+// command to monitor is given after an `--exec` argument.
 #[derive(Debug)]
-struct Database(BTreeMap<Pid, Pinfo>);
+struct Options {
+    format: Format,
+    sort: Sort,
+    // How often to search for new child processes and to measure their
+    // locked memory.
+    config: Config,
+    // Fail the run if any child process locks more than this many kbs.
+    max_locked_kb: Option<u64>,
+    // Warn if the soft locked memory limit is at or below this many kbs.
+    min_soft_limit_kb: u64,
+    // Warn when a child process locks at least this percentage of a locked
+    // memory limit.
+    warn_percent: u64,
+    // The limit used by the limit check.
+    against: Against,
+    // Record and output a time series of each child processes' locked
+    // memory.
+    timeseries: bool,
+    // Write the time series to this file as CSV rather than appending it to
+    // the report.
+    timeseries_file: Option<String>,
+    // Write each measurement as a line of JSON to this path as it is taken,
+    // or to stdout if the path is `-`.
+    stream_jsonl: Option<String>,
+    // Show memory in kbs rather than human readable units.
+    raw_kb: bool,
+    // Show the min and average locked memory of each child process in the
+    // table.
+    stats: bool,
+    // Show each child processes' full command line rather than its name.
+    full_cmd: bool,
+    // Truncate the names in the table to this many characters.
+    name_width: Option<usize>,
+    // Only show this many of the child processes in the table.
+    top: Option<usize>,
+    // Measure the locked memory of each child processes' threads.
+    per_thread: bool,
+    // Merge the child processes with the same name into one row of the
+    // table.
+    group_by_name: bool,
+    // Only report the child processes whose name or command line contains
+    // this.
+    filter: Option<String>,
+    // Show the max `VmRSS` and `VmHWM` of each child process in the table.
+    show_rss: bool,
+    // Show the number of samples taken of each child process in the table.
+    show_samples: bool,
+    // Show whether each child processes' locked memory returned to zero in
+    // the table.
+    show_released: bool,
+    // Leave the child processes that never locked memory out of the table.
+    hide_zero: bool,
+    // Write the measurements report to this file instead of stdout.
+    output_file: Option<String>,
+    // Kill the monitored command if it runs for longer than this.
+    timeout: Option<Duration>,
+    // Also measure the monitored command itself (e.g. `cargo test`), not just
+    // its descendants.
+    include_parent: bool,
+    // Run the monitored command this many times, reporting the worst case
+    // locked memory of every run.
+    runs: u32,
+    // Stop running the monitored command as soon as a run locks more than
+    // `max_locked_kb`, running it at most `runs` times.
+    repeat_until_spike: bool,
+    // Don't print the monitored command's output.
+    quiet: bool,
+    // Redraw a live view of the measurements while the command runs.
+    tui: bool,
+    // Also write a summary of the run to syslog.
+    syslog: bool,
+    // Report the monitor's own CPU time and number of reads.
+    self_profile: bool,
+    // Never color the output, even when writing to a terminal.
+    no_color: bool,
+    // Log what the worker threads are doing to stderr.
+    verbose: bool,
+    // Fail the run if no child processes were observed.
+    require_samples: bool,
+    // Fail if the locked memory limits can't be read, rather than treating
+    // them as unlimited.
+    require_limit: bool,
+    // A previous run's JSON report to compare this run against.
+    baseline: Option<String>,
+    // Fail the run if a process locks more than this many kbs more than it
+    // did in the baseline run.
+    regression_threshold_kb: u64,
+    // Only check that the environment can be monitored, without running
+    // anything.
+    check: bool,
+    // The names of the child processes to ignore.
+    ignore: Vec<String>,
+    cargo_test_args: Vec<String>,
+    // The command (and its arguments) to monitor instead of `cargo test`.
+    command: Option<Vec<String>>,
+    // Only pass the variables in `CLEAN_ENV_VARS` (plus `env`) on to the
+    // monitored command, rather than our whole environment.
+    clean_env: bool,
+    // Extra environment variables to set for the monitored command.
+    env: Vec<(String, String)>,
+    // An already running process to monitor instead of running a command.
+    watch_pid: Option<Pid>,
+}
+
+// The environment variables kept with `--clean-env`, along with any starting
+// with `RUSTUP_` (e.g. `RUSTUP_TOOLCHAIN`).
+const CLEAN_ENV_VARS: [&str; 4] = ["PATH", "HOME", "CARGO", "CARGO_HOME"];
+
+// Returns the environment to run the monitored command with: `vars` (our
+// environment), only keeping the variables needed to run cargo if `clean` is
+// set, followed by `extra`.
+fn child_env(
+    vars: impl Iterator<Item = (String, String)>,
+    clean: bool,
+    extra: &[(String, String)],
+) -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = vars
+        .filter(|(key, _)| {
+            !clean || CLEAN_ENV_VARS.contains(&key.as_str()) || key.starts_with("RUSTUP_")
+        })
+        .collect();
+    env.extend_from_slice(extra);
+    env
+}
+
+// Splits a comma separated list of process names.
+fn split_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .filter(|pname| !pname.is_empty())
+        .map(|pname| pname.to_string())
+        .collect()
+}
 
-impl Database {
+// Parses the `KEY=VAL` value of an `--env` option.
+fn parse_env(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, val)) if !key.is_empty() => Ok((key.to_string(), val.to_string())),
+        _ => Err(format!("invalid `--env`, expected `KEY=VAL`: `{}`", value)),
+    }
+}
+
+// Parses the value of an option given in milliseconds.
+fn parse_ms(option: &str, value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("invalid `{}`: `{}`", option, value))
+}
+
+// The state of parsing the `cargo mlocktest` options, which are set by the
+// config file and then by the command line: the options set so far, and those
+// which are only resolved once every option has been seen.
+struct Parser {
+    options: Options,
+    ignore: Option<Vec<String>>,
+    ignore_add: Vec<String>,
+    no_ignore: bool,
+    max_iters: Option<u32>,
+}
+
+impl Parser {
     fn new() -> Self {
-        Database(BTreeMap::new())
+        let options = Options {
+            format: Format::Table,
+            sort: Sort::Locked,
+            config: Config::default(),
+            max_locked_kb: None,
+            min_soft_limit_kb: DEFAULT_MIN_SOFT_LIMIT_KB,
+            warn_percent: LIMIT_WARN_PERCENT,
+            against: Against::Soft,
+            timeseries: false,
+            timeseries_file: None,
+            stream_jsonl: None,
+            raw_kb: false,
+            stats: false,
+            full_cmd: false,
+            name_width: None,
+            top: None,
+            per_thread: false,
+            group_by_name: false,
+            filter: None,
+            show_rss: false,
+            show_samples: false,
+            show_released: false,
+            hide_zero: false,
+            output_file: None,
+            timeout: None,
+            include_parent: false,
+            runs: 1,
+            repeat_until_spike: false,
+            quiet: false,
+            tui: false,
+            syslog: false,
+            self_profile: false,
+            no_color: false,
+            verbose: false,
+            require_samples: false,
+            require_limit: false,
+            baseline: None,
+            regression_threshold_kb: 0,
+            check: false,
+            ignore: vec![],
+            cargo_test_args: vec![],
+            command: None,
+            clean_env: false,
+            env: vec![],
+            watch_pid: None,
+        };
+        Parser { options, ignore: None, ignore_add: vec![], no_ignore: false, max_iters: None }
     }
 
-    fn contains(&self, pid: &Pid) -> bool {
-        self.0.contains_key(pid)
+    // Sets the option `--name`, or `--name=value` if a `value` is given.
+    // Returns `false` if there is no such option, or if it needs a value and
+    // none was given (or the other way around).
+    fn set(&mut self, name: &str, value: Option<&str>) -> Result<bool, String> {
+        let Parser { options: o, ignore, ignore_add, no_ignore, max_iters } = self;
+        match (name, value) {
+            ("format", Some(value)) => o.format = value.parse()?,
+            ("sort", Some(value)) => o.sort = value.parse()?,
+            ("interval-ms", Some(value)) => {
+                let interval = parse_ms("--interval-ms", value)?;
+                o.config.discovery_interval = interval;
+                o.config.measurement_interval = interval;
+            }
+            ("discovery-ms", Some(value)) => {
+                o.config.discovery_interval = parse_ms("--discovery-ms", value)?;
+            }
+            ("measure-ms", Some(value)) => {
+                o.config.measurement_interval = parse_ms("--measure-ms", value)?;
+            }
+            ("field", Some(value)) => {
+                let valid = |c: char| c.is_ascii_alphanumeric() || c == '_';
+                if value.is_empty() || !value.chars().all(valid) {
+                    return Err(format!("invalid `--field`: `{}`", value));
+                }
+                o.config.field = value.to_string();
+            }
+            ("procfs", Some(value)) => {
+                if value.is_empty() {
+                    return Err("invalid `--procfs`: expected a path".to_string());
+                }
+                o.config.procfs = value.trim_end_matches('/').to_string();
+            }
+            ("config", Some(_)) => {
+                // The config file is loaded by `load_config`.
+            }
+            ("stop-after", Some(value)) => {
+                if value.is_empty() {
+                    return Err("invalid `--stop-after`: expected a process name".to_string());
+                }
+                o.config.stop_after = Some(value.to_string());
+            }
+            ("proc-events", None) => o.config.proc_events = true,
+            ("fast-poll-ms", Some(value)) => {
+                o.config.fast_discovery_window = parse_ms("--fast-poll-ms", value)?;
+            }
+            ("timeseries", None) => o.timeseries = true,
+            ("timeseries", Some(value)) => {
+                if value.is_empty() {
+                    return Err("invalid `--timeseries`: expected a path".to_string());
+                }
+                o.timeseries = true;
+                o.timeseries_file = Some(value.to_string());
+            }
+            ("stream-jsonl", None) => o.stream_jsonl = Some("-".to_string()),
+            ("stream-jsonl", Some(value)) => o.stream_jsonl = Some(value.to_string()),
+            ("raw-kb", None) => o.raw_kb = true,
+            ("stats", None) => o.stats = true,
+            ("show-rss", None) => o.show_rss = true,
+            ("show-samples", None) => o.show_samples = true,
+            ("show-released", None) => o.show_released = true,
+            ("hide-zero", None) => o.hide_zero = true,
+            ("full-cmd", None) => o.full_cmd = true,
+            ("name-width", Some(value)) => {
+                o.name_width = match value.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("invalid `--name-width`: `{}`", value)),
+                };
+            }
+            ("top", Some(value)) => {
+                let n = value
+                    .parse()
+                    .map_err(|_| format!("invalid `--top`: `{}`", value))?;
+                o.top = Some(n);
+            }
+            ("per-thread", None) => o.per_thread = true,
+            ("group-by-name", None) => o.group_by_name = true,
+            ("filter", Some(value)) => o.filter = Some(value.to_string()),
+            ("output-file", Some(value)) => o.output_file = Some(value.to_string()),
+            ("timeout-secs", Some(value)) => {
+                let secs = value
+                    .parse()
+                    .map_err(|_| format!("invalid `--timeout-secs`: `{}`", value))?;
+                o.timeout = Some(Duration::from_secs(secs));
+            }
+            ("include-parent", None) => o.include_parent = true,
+            ("runs", Some(value)) => {
+                o.runs = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid `--runs`: `{}`", value)),
+                };
+            }
+            ("repeat-until-spike", None) => o.repeat_until_spike = true,
+            ("max-iters", Some(value)) => {
+                *max_iters = match value.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => return Err(format!("invalid `--max-iters`: `{}`", value)),
+                };
+            }
+            ("clean-env", None) => o.clean_env = true,
+            ("env", Some(value)) => o.env.push(parse_env(value)?),
+            ("quiet", None) => o.quiet = true,
+            ("tui", None) => o.tui = true,
+            ("syslog", None) => o.syslog = true,
+            ("self-profile", None) => o.self_profile = true,
+            ("no-color", None) => o.no_color = true,
+            ("verbose", None) => o.verbose = true,
+            ("require-samples", None) => o.require_samples = true,
+            ("require-limit", None) => o.require_limit = true,
+            ("baseline", Some(value)) => o.baseline = Some(value.to_string()),
+            ("regression-threshold-kb", Some(value)) => {
+                o.regression_threshold_kb = value
+                    .parse()
+                    .map_err(|_| format!("invalid `--regression-threshold-kb`: `{}`", value))?;
+            }
+            ("check", None) => o.check = true,
+            ("ignore", Some(value)) => *ignore = Some(split_names(value)),
+            ("ignore-add", Some(value)) => ignore_add.extend(split_names(value)),
+            ("no-ignore", None) => *no_ignore = true,
+            ("watch-pid", Some(value)) => {
+                let pid = value
+                    .parse()
+                    .map_err(|_| format!("invalid `--watch-pid`: `{}`", value))?;
+                o.watch_pid = Some(pid);
+            }
+            ("max-locked-kb", Some(value)) => {
+                let kbs = value
+                    .parse()
+                    .map_err(|_| format!("invalid `--max-locked-kb`: `{}`", value))?;
+                o.max_locked_kb = Some(kbs);
+            }
+            ("warn-percent", Some(value)) => {
+                o.warn_percent = match value.parse() {
+                    Ok(n) if (1..=100).contains(&n) => n,
+                    _ => return Err(format!("invalid `--warn-percent`: `{}`", value)),
+                };
+            }
+            ("against", Some(value)) => o.against = value.parse()?,
+            ("min-soft-limit-kb", Some(value)) => {
+                o.min_soft_limit_kb = value
+                    .parse()
+                    .map_err(|_| format!("invalid `--min-soft-limit-kb`: `{}`", value))?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
     }
 
-    fn new_child_process(&mut self, pid: Pid, pname: Pname) {
-        self.0.insert(pid, Pinfo { pname, max_locked: 0 });
+    // Parses the command line arguments (not including the program name).
+    // Everything after an `--exec` argument is the command to monitor in place
+    // of `cargo test`, and everything after a `--` argument is forwarded to
+    // `cargo test` as is. Any other argument that isn't one of our options is
+    // also forwarded to `cargo test`. When run as `cargo mlocktest`, cargo
+    // passes the subcommand's name as the first argument, which is skipped.
+    fn parse_args<I>(&mut self, args: I) -> Result<(), String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("mlocktest") {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                // The remaining arguments are for the test binaries.
+                self.options.cargo_test_args.push(arg);
+                self.options.cargo_test_args.extend(args.by_ref());
+            } else if arg == "--exec" {
+                let cmd: Vec<String> = args.by_ref().collect();
+                if cmd.is_empty() {
+                    return Err("no command given after `--exec`".to_string());
+                }
+                self.options.command = Some(cmd);
+            } else if arg == "--env" {
+                let value = args.next().ok_or("`--env` expects a `KEY=VAL` argument")?;
+                self.options.env.push(parse_env(&value)?);
+            } else if arg == "-v" {
+                self.options.verbose = true;
+            } else {
+                let option = arg.strip_prefix("--").map(|option| match option.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (option, None),
+                });
+                let is_option = match option {
+                    Some((name, value)) => self.set(name, value)?,
+                    None => false,
+                };
+                if !is_option {
+                    self.options.cargo_test_args.push(arg);
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn update(&mut self, pid: Pid, kbs_locked: u64) {
-        if let Some(pinfo) = self.0.get_mut(&pid) {
-            if kbs_locked > pinfo.max_locked {
-                pinfo.max_locked = kbs_locked;
+    // Checks the options that conflict with or require others, and resolves
+    // the options that depend on others.
+    fn finish(self) -> Result<Options, String> {
+        let Parser { mut options, ignore, ignore_add, no_ignore, max_iters } = self;
+        let o = &mut options;
+        if o.command.is_some() && !o.cargo_test_args.is_empty() {
+            return Err(format!(
+                "`--exec` can't be used with `cargo test` arguments: `{}`",
+                o.cargo_test_args.join(" "),
+            ));
+        }
+        if o.watch_pid.is_some() && (o.command.is_some() || !o.cargo_test_args.is_empty()) {
+            return Err("`--watch-pid` can't be used with a command to run".to_string());
+        }
+        if o.watch_pid.is_some() && (o.clean_env || !o.env.is_empty()) {
+            return Err("`--watch-pid` can't be used with `--clean-env` or `--env`".to_string());
+        }
+        // The time series is CSV, which would make the report unparseable.
+        let machine_readable = o.format.is_machine_readable();
+        if o.timeseries && o.timeseries_file.is_none() && machine_readable {
+            return Err(
+                "`--timeseries` can only be added to a `table` or `markdown` report; use \
+                 `--timeseries=<path>` to write it to its own file"
+                    .to_string(),
+            );
+        }
+        // Both would be written to stdout, where they can't be told apart.
+        let report_on_stdout = machine_readable && o.output_file.is_none();
+        if o.stream_jsonl.as_deref() == Some("-") && report_on_stdout {
+            return Err(
+                "`--stream-jsonl` can't write to stdout along with the report; give it a \
+                 path or pass `--output-file`"
+                    .to_string(),
+            );
+        }
+        if o.watch_pid.is_some() && o.runs > 1 {
+            return Err("`--watch-pid` can't be used with `--runs`".to_string());
+        }
+        // The proc connector reports the pids of the initial pid namespace,
+        // which needn't match those in another proc filesystem.
+        if o.config.proc_events && o.config.procfs != DEFAULT_PROCFS {
+            return Err("`--proc-events` can't be used with `--procfs`".to_string());
+        }
+        if o.repeat_until_spike {
+            if o.max_locked_kb.is_none() {
+                return Err("`--repeat-until-spike` requires `--max-locked-kb`".to_string());
+            }
+            if o.watch_pid.is_some() || o.runs > 1 {
+                return Err(
+                    "`--repeat-until-spike` can't be used with `--watch-pid` or `--runs`"
+                        .to_string(),
+                );
             }
+            o.runs = max_iters.unwrap_or(DEFAULT_MAX_ITERS);
+        } else if max_iters.is_some() {
+            return Err("`--max-iters` requires `--repeat-until-spike`".to_string());
+        }
+        if no_ignore && (ignore.is_some() || !ignore_add.is_empty()) {
+            return Err(
+                "`--no-ignore` can't be used with `--ignore` or `--ignore-add`".to_string(),
+            );
         }
+        let ignore = if no_ignore { Some(vec![]) } else { ignore };
+        o.ignore = ignore.unwrap_or_else(|| {
+            IGNORE_CHILD_PROCS.iter().map(|pname| pname.to_string()).collect()
+        });
+        o.ignore.extend(ignore_add);
+        Ok(options)
     }
+}
 
-    fn table(&self) -> String {
-        let col1_heading = "Process Name";
-        let col2_heading = "Max Locked Memory (kb)";
-        let col1_heading_len = col1_heading.chars().count();
-        let col2_heading_len = col2_heading.chars().count();
-        let min_col2_start = col1_heading_len + COLUMN_BUFFER;
-        let col2_start = self.0
-            .values()
-            .fold(min_col2_start, |longest, pinfo| {
-                match pinfo.pname.chars().count() + COLUMN_BUFFER {
-                    n_chars if n_chars > longest => n_chars,
-                    _ => longest,
-                }
-            });
-        let heading_whitespace: String = (0..col2_start - col1_heading_len)
-            .map(|_| ' ')
+// Parses the `cargo mlocktest` options set by the config file (see
+// `load_config`) and then by the command line arguments, which override them.
+fn load_options(args: Vec<String>) -> Result<Options, String> {
+    let mut parser = Parser::new();
+    load_config(&args, &mut parser)?;
+    parser.parse_args(args)?;
+    parser.finish()
+}
+
+// Returns whether the `cargo test` arguments contain a `--manifest-path`
+// option. Arguments after a `--` are passed on to the test binaries, so they
+// are not options for `cargo test`.
+fn has_manifest_path(cargo_test_args: &[String]) -> bool {
+    cargo_test_args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "--manifest-path" || arg.starts_with("--manifest-path="))
+}
+
+// Returns the arguments to run `cargo test` with: the `cargo test` arguments,
+// after a `--manifest-path` option if a `manifest` is given. A `--` and the
+// arguments after it are kept as is, so that they reach the test binaries.
+fn cargo_test_command(cargo_test_args: Vec<String>, manifest: Option<&Path>) -> Vec<String> {
+    let mut args = vec!["test".to_string()];
+    if let Some(manifest) = manifest {
+        args.push(format!("--manifest-path={}", manifest.display()));
+    }
+    args.extend(cargo_test_args);
+    args
+}
+
+// The config file that is loaded, if it exists, unless one is given with
+// `--config`.
+const DEFAULT_CONFIG_FILE: &str = "mlocktest.toml";
+
+// A value in a config file: a flag, or an option's value.
+#[derive(Debug, PartialEq)]
+enum ConfigValue {
+    Flag(bool),
+    Value(String),
+}
+
+// Sets the options from the config file given with `--config` (before any
+// `--` or `--exec`), or else from `mlocktest.toml` in the current directory if
+// it exists. They are set before the command line arguments are parsed, which
+// so override them.
+fn load_config(args: &[String], parser: &mut Parser) -> Result<(), String> {
+    let explicit = args
+        .iter()
+        .take_while(|arg| *arg != "--" && *arg != "--exec")
+        .find_map(|arg| arg.strip_prefix("--config="));
+    let path = match explicit {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG_FILE).exists() => DEFAULT_CONFIG_FILE,
+        None => return Ok(()),
+    };
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read the config file `{}`: {}", path, e))?;
+    apply_config(&contents, parser)
+        .map_err(|e| format!("invalid config file `{}`: {}", path, e))
+}
+
+// Sets the options in the contents of a config file. The file is a small
+// subset of TOML: each `key = value` line sets the option `--key` (reading
+// `_`s as `-`s), where `true` sets a flag and `false` leaves it unset, a
+// string or integer is the option's value, and an array of them is a comma
+// separated list. Each `KEY = "VAL"` line in an `[env]` table sets an
+// environment variable, as with `--env`.
+fn apply_config(contents: &str, parser: &mut Parser) -> Result<(), String> {
+    let mut in_env = false;
+    for (i, line) in contents.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let err = |msg: String| format!("line {}: {}", i + 1, msg);
+        if line.is_empty() {
+            continue;
+        } else if line == "[env]" {
+            in_env = true;
+            continue;
+        } else if line.starts_with('[') {
+            return Err(err(format!("unsupported table `{}`", line)));
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return Err(err("expected `key = value`".to_string())),
+        };
+        let valid_key = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+        if key.is_empty() || !key.chars().all(valid_key) {
+            return Err(err(format!("invalid key `{}`", key)));
+        }
+        let value = parse_config_value(value)
+            .ok_or_else(|| err(format!("invalid value for `{}`: `{}`", key, value)))?;
+        let option = key.replace('_', "-");
+        let is_set = match value {
+            ConfigValue::Value(value) if in_env => {
+                parser.options.env.push((key.to_string(), value));
+                true
+            }
+            _ if in_env => return Err(err(format!("`{}` must be a string", key))),
+            // `--config` and `--exec` only make sense on the command line.
+            _ if option == "config" || option == "exec" => {
+                return Err(err(format!("a config file can't set `{}`", key)));
+            }
+            ConfigValue::Flag(true) => parser.set(&option, None).map_err(err)?,
+            ConfigValue::Flag(false) => true,
+            ConfigValue::Value(value) => parser.set(&option, Some(&value)).map_err(err)?,
+        };
+        if !is_set {
+            return Err(err(format!("unknown option `{}`, or a value of the wrong kind", key)));
+        }
+    }
+    Ok(())
+}
+
+// Removes a `#` comment (outside of a string) from the end of a line.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            None if c == '#' => return &line[..i],
+            None if c == '"' || c == '\'' => quote = Some(c),
+            Some(q) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+// Parses a config file value: `true` or `false`, a string, an integer, or an
+// array of strings and integers. Escapes in strings aren't supported.
+fn parse_config_value(value: &str) -> Option<ConfigValue> {
+    match value {
+        "true" => return Some(ConfigValue::Flag(true)),
+        "false" => return Some(ConfigValue::Flag(false)),
+        _ => {}
+    }
+    if let Some(items) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        let items: Option<Vec<String>> = items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(parse_config_scalar)
             .collect();
-        let heading = format!(
-            "{}{}{}",
-            col1_heading,
-            heading_whitespace,
-            col2_heading,
-        );
-        let top_border = format!(
-            "{}{}{}",
-            (0..col1_heading_len).map(|_| '=').collect::<String>(),
-            heading_whitespace,
-            (0..col2_heading_len).map(|_| '=').collect::<String>(),
-        );
-        let mut stdout = format!("\n{}\n{}\n", heading, top_border);
-        for Pinfo { pname, max_locked } in self.0.values() {
-            let pname_len = pname.chars().count();
-            let whitespace: String = (0..col2_start - pname_len)
-                .map(|_| ' ')
-                .collect();
-            let line = format!("{}{}{}\n", pname, whitespace, max_locked);
-            stdout.push_str(&line);
+        return items.map(|items| ConfigValue::Value(items.join(",")));
+    }
+    parse_config_scalar(value).map(ConfigValue::Value)
+}
+
+fn parse_config_scalar(value: &str) -> Option<String> {
+    for quote in ['"', '\''] {
+        if let Some(s) = value.strip_prefix(quote).and_then(|v| v.strip_suffix(quote)) {
+            if s.contains(quote) || s.contains('\\') {
+                return None;
+            }
+            return Some(s.to_string());
         }
-        let table_width = col2_start + col2_heading_len;
-        let bottom_border: String = (0..table_width).map(|_| '=').collect();
-        stdout.push_str(&bottom_border);
-        stdout
     }
+    value.parse::<u64>().ok().map(|n| n.to_string())
 }
 
-#[derive(Debug)]
-enum Limit {
-    Kb(u64),
-    Unlimited,
+// Set by our signal handler to the signal we received, or 0 if we haven't
+// received one.
+static INTERRUPTED_BY: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    INTERRUPTED_BY.store(signal, Ordering::SeqCst);
+}
+
+// Returns the signal that interrupted us, if any.
+fn interrupted_by() -> Option<libc::c_int> {
+    match INTERRUPTED_BY.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
 }
 
-impl Display for Limit {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            Limit::Kb(kbs) => write!(f, "{}", kbs),
-            _ => write!(f, "unlimited"),
+fn interrupted() -> bool {
+    interrupted_by().is_some()
+}
+
+// Installs a handler for SIGINT and SIGTERM so that hitting Ctrl-C, or being
+// stopped by e.g. a container orchestrator, stops the monitored command but
+// still prints the measurements collected up to that point. Both signals
+// take the same shutdown path.
+fn handle_signals() {
+    let handler = on_signal as extern "C" fn(libc::c_int);
+    for signal in &[libc::SIGINT, libc::SIGTERM] {
+        unsafe {
+            libc::signal(*signal, handler as libc::sighandler_t);
         }
     }
 }
 
-impl FromStr for Limit {
-    type Err = ();
+// Set when the monitored command runs past the `--timeout-secs` deadline.
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s == "unlimited" {
-            Ok(Limit::Unlimited)
-        } else {
-            let n_bytes: u64 = s.parse::<u64>().map_err(|_| ())?;
-            Ok(Limit::Kb(n_bytes / 1024))
+// Kills the monitored command along with all of its descendants, e.g. the
+// test binaries run by `cargo test`, which would otherwise keep running.
+#[cfg(target_os = "linux")]
+fn kill_tree(child: &mut Child) {
+    for entry in run_ps(child.id(), false, &[]) {
+        unsafe {
+            libc::kill(entry.pid as libc::pid_t, libc::SIGKILL);
         }
     }
+    let _ = child.kill();
 }
 
-#[derive(Debug)]
-struct MlockLimit {
-    soft: Limit,
-    hard: Limit,
-}
-
-fn run_prlimit() -> MlockLimit {
-    let output = Command::new("prlimit")
-        .args(&["--memlock", "--output=SOFT,HARD", "--noheadings"])
-        .output()
-        .map(|output| String::from_utf8(output.stdout).unwrap())
-        .unwrap_or_else(|e| panic!("Subprocess failed: `ulimit`: {:?}", e));
-    let split: Vec<&str> = output.split_whitespace().collect();
-    let soft = Limit::from_str(split[0]).unwrap();
-    let hard = Limit::from_str(split[1]).unwrap();
-    MlockLimit { soft, hard }
-}
-
-fn run_ps(cargo_test_pid: Pid) -> Vec<(Pid, Pname)> {
-    let mut ps = vec![];
-    let ppid = cargo_test_pid.to_string();
-    let output = Command::new("ps")
-        .args(&["-f", "--ppid", &ppid])
-        .output()
-        .map(|output| String::from_utf8(output.stdout).unwrap())
-        .expect("Subprocess failed: `ps`");
-    for line in output.trim().lines().skip(1) {
-        let split: Vec<&str> = line.split_whitespace().collect();
-        let pid: Pid = split[1].parse().unwrap();
-        let pname: Pname = split[7]
-            .split_whitespace()
-            .nth(0)
-            .unwrap()
-            .split('/')
-            .last()
-            .unwrap()
-            .to_string();
-        if !IGNORE_CHILD_PROCS.contains(&pname.as_ref()) {
-            ps.push((pid, pname));
-        }
-    }
-    ps
-}
-
-// Launches a thread that continuously calls `ps`, updates the shared
-// `child_pids` vector, and inserts the child processes' pids and names
-// into the measurements database.
-fn launch_ps_thread(
-    cargo_test_pid: Arc<Mutex<Option<Pid>>>,
-    child_pids: Arc<Mutex<Vec<Pid>>>,
-    db: Arc<Mutex<Database>>,
-    done: Arc<AtomicBool>,
-) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let cargo_test_pid = loop {
-            if let Some(pid) = *cargo_test_pid.lock().unwrap() {
-                break pid;
-            }
-        };
-        while !done.load(Ordering::Relaxed) {
-            let ps = run_ps(cargo_test_pid);
-            *child_pids.lock().unwrap() = ps.iter().map(|(pid, _pname)| *pid).collect();
-            let mut db = db.lock().unwrap();
-            for (pid, pname) in ps {
-                if !db.contains(&pid) {
-                    db.new_child_process(pid, pname);
-                }
-            }
-            thread::sleep(Duration::from_millis(100));
+// Waits for the monitored command to exit. If we receive a SIGINT or SIGTERM
+// while waiting, if the `deadline` passes, or if `stopped` is set (by the ps
+// thread, with `--stop-after`), the command and its descendants are killed.
+// Unlike a Ctrl-C, a SIGTERM is only sent to us, so the descendants wouldn't
+// otherwise stop.
+#[cfg(target_os = "linux")]
+fn wait_for_child(
+    child: &mut Child,
+    deadline: Option<Instant>,
+    stopped: &AtomicBool,
+) -> io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
         }
-    })
+        if interrupted() || stopped.load(Ordering::Acquire) {
+            kill_tree(child);
+            return child.wait();
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            TIMED_OUT.store(true, Ordering::SeqCst);
+            kill_tree(child);
+            return child.wait();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Builds the live view's table of each processes' most recently measured and
+// peak memory, the processes with the highest current value first.
+fn live_table(db: &Database, metric: &str, raw_kb: bool) -> Table {
+    let format_mem = |kbs: u64| if raw_kb { kbs.to_string() } else { format_kbs(kbs) };
+    let mut pinfos: Vec<&Pinfo> = db.pinfos.values().collect();
+    pinfos.sort_by(|a, b| {
+        b.last_locked.cmp(&a.last_locked).then_with(|| b.max_locked.cmp(&a.max_locked))
+    });
+    let rows = pinfos
+        .into_iter()
+        .map(|pinfo| {
+            let (current, peak) = (pinfo.last_locked, pinfo.max_locked);
+            vec![pinfo.pname.clone(), format_mem(current), format_mem(peak)]
+        })
+        .collect();
+    let unit = if raw_kb { " (kb)" } else { "" };
+    Table {
+        headings: vec![
+            "Process Name".to_string(),
+            format!("Current {}{}", metric, unit),
+            format!("Peak {}{}", metric, unit),
+        ],
+        rows,
+        colors: vec![],
+        n_hidden: 0,
+    }
 }
 
-// Launches a thread that continuously reads each child processes'
-// "status" file, parses each file to get the ammount memory locked by that
-// child process, then updates the database with the locked memory
-// information.
-fn launch_measurements_thread(
-    cargo_test_pid: Arc<Mutex<Option<Pid>>>,
-    child_pids: Arc<Mutex<Vec<Pid>>>,
-    db: Arc<Mutex<Database>>,
-    done: Arc<AtomicBool>,
-) -> JoinHandle<()> {
+// Redraws the live view of `title` and the measurements in `shared` every
+// `interval` until the worker threads are stopped.
+fn launch_tui_thread(
+    shared: Shared,
+    title: String,
+    metric: String,
+    raw_kb: bool,
+    interval: Duration,
+) -> thread::JoinHandle<()> {
     thread::spawn(move || {
-        while cargo_test_pid.lock().unwrap().is_none() {
-            thread::sleep(Duration::from_millis(1));
-        }
-        while !done.load(Ordering::Relaxed) {
-            for child_pid in child_pids.lock().unwrap().iter() {
-                if let Some(kbs_locked) = parse_status_file(*child_pid) {
-                    db.lock().unwrap().update(*child_pid, kbs_locked);
-                }
-            }
-            thread::sleep(Duration::from_millis(1));
+        while !shared.done.load(Ordering::Acquire) {
+            let table = live_table(&shared.db.lock().unwrap(), &metric, raw_kb);
+            // Move the cursor to the top left and clear the screen, then
+            // redraw.
+            print!("\x1b[H\x1b[2J{}\n{}\n", title, table.render(Renderer::Ascii));
+            let _ = io::stdout().flush();
+            thread::sleep(interval);
         }
     })
 }
 
-// Reads a processes' "status" file; parsing it for the ammount of memory
-// currently locked by the process.
-fn parse_status_file(pid: Pid) -> Option<u64> {
-    let path = format!("/proc/{}/status", pid);
-    let file = fs::read_to_string(path).ok()?;
-    for line in file.lines() {
-        if line.starts_with("VmLck") {
-            match line.trim().split_whitespace().nth(1) {
-                Some(s) => return s.parse().ok(),
-                _ => return None,
+// Builds a table of the changes in max locked memory since the baseline run,
+// flagging the increases of more than `threshold` kbs.
+fn baseline_table(deltas: &[Delta], threshold: u64) -> Table {
+    let kbs = |kbs: Option<u64>| kbs.map_or("-".to_string(), |kbs| kbs.to_string());
+    let rows = deltas
+        .iter()
+        .map(|delta| {
+            let diff = match (delta.baseline, delta.current) {
+                (Some(baseline), Some(current)) if current >= baseline => {
+                    format!("+{}", current - baseline)
+                }
+                (Some(baseline), Some(current)) => format!("-{}", baseline - current),
+                _ => "-".to_string(),
+            };
+            let change = match delta.change() {
+                Change::Increased if delta.increase() > threshold => {
+                    "increased (REGRESSION)".to_string()
+                }
+                change => change.to_string(),
             };
+            let pname = delta.pname.clone();
+            vec![pname, kbs(delta.baseline), kbs(delta.current), diff, change]
+        })
+        .collect();
+    let headings = ["Process Name", "Baseline (kb)", "Current (kb)", "Delta (kb)", "Change"];
+    Table {
+        headings: headings.iter().map(|heading| heading.to_string()).collect(),
+        rows,
+        colors: vec![],
+        n_hidden: 0,
+    }
+}
+
+// Returns the name of a signal, e.g. "SIGKILL".
+#[cfg(unix)]
+fn signal_name(signal: libc::c_int) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+// Describes how the monitored command exited, for the run summary: the exit
+// `code` it exited with, or else the `signal` it was terminated by. If we
+// killed it ourselves (with SIGKILL), `killed_for` is why. An exit code above
+// `EXIT_SIGNAL_BASE` is what a shell exits with when its command is terminated
+// by a signal, so that signal is named too. We don't get the exit code of a
+// watched process, as it isn't our child.
+#[cfg(unix)]
+fn describe_exit(
+    watched: bool,
+    code: Option<i32>,
+    signal: Option<libc::c_int>,
+    killed_for: Option<&str>,
+) -> String {
+    match (code, signal, killed_for) {
+        _ if watched => "exited".to_string(),
+        (Some(code), _, _) if code > EXIT_SIGNAL_BASE && code < EXIT_SIGNAL_BASE + 32 => {
+            let signal = signal_name(code - EXIT_SIGNAL_BASE);
+            format!("exited with code {}, the code for being terminated by {}", code, signal)
+        }
+        (Some(code), _, _) => format!("exited with code {}", code),
+        (None, Some(libc::SIGKILL), Some(reason)) => {
+            format!("was killed by `cargo mlocktest` with SIGKILL ({})", reason)
+        }
+        (None, Some(signal), _) => format!("was terminated by {}", signal_name(signal)),
+        (None, None, _) => "was terminated by a signal".to_string(),
+    }
+}
+
+// Checks that we are able to monitor locked memory, i.e. that we can get the
+// locked memory limits and read processes' locked memory from `/proc`, and
+// prints the results. Returns whether the checks passed.
+#[cfg(target_os = "linux")]
+fn run_check() -> bool {
+    let mlock_limit = match run_prlimit() {
+        Ok(mlock_limit) => mlock_limit,
+        Err(e) => {
+            println!("limits: failed to get the locked memory limits: {}", e);
+            return false;
+        }
+    };
+    println!("Locked memory limit (soft, kb): {}", mlock_limit.soft);
+    println!("Lock memory limit (hard, kb): {}", mlock_limit.hard);
+    match has_cap_ipc_lock() {
+        Some(true) => println!("CAP_IPC_LOCK: yes (the limits don't apply)"),
+        Some(false) => println!("CAP_IPC_LOCK: no"),
+        None => println!("CAP_IPC_LOCK: unknown"),
+    }
+    match parse_status_file(process::id()) {
+        Some(status) => {
+            println!("/proc: ok (this process has {} kb locked)", status.vmlck);
+            true
+        }
+        None => {
+            println!("/proc: failed to read `VmLck` from `/proc/self/status`");
+            false
+        }
+    }
+}
+
+// Returns the path of the `Cargo.toml` in `dir` or in its nearest parent
+// directory that has one.
+fn find_manifest(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+}
+
+// Returns whether a process with the given pid exists in `procfs`.
+fn is_running(procfs: &str, pid: Pid) -> bool {
+    Path::new(&format!("{}/{}", procfs, pid)).exists()
+}
+
+// Waits for a process which isn't our child to exit, or until we receive a
+// SIGINT. We stop waiting (without killing the process) once the `deadline`
+// passes.
+fn wait_for_pid(procfs: &str, pid: Pid, deadline: Option<Instant>, stopped: &AtomicBool) {
+    while is_running(procfs, pid) && !interrupted() && !stopped.load(Ordering::Acquire) {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            TIMED_OUT.store(true, Ordering::SeqCst);
+            return;
         }
+        thread::sleep(Duration::from_millis(10));
     }
-    None
 }
 
+#[cfg(not(target_os = "linux"))]
 fn main() {
-    println!("CURRENT CWD => {:?}", env::current_dir());
-    println!("CURRENT EXE => {:?}", env::current_exe());
-
-    // Initialize the values that will be shared between threads.
-    let cargo_test_pid: Arc<Mutex<Option<Pid>>> = Arc::new(Mutex::new(None));
-    let child_pids: Arc<Mutex<Vec<Pid>>> = Arc::new(Mutex::new(vec![]));
-    let db = Arc::new(Mutex::new(Database::new()));
-    let done = Arc::new(AtomicBool::new(false));
-
-    // Start the worker threads.
-    let ps_thread = launch_ps_thread(
-        cargo_test_pid.clone(),
-        child_pids.clone(),
-        db.clone(),
-        done.clone()
-    );
-    let file_reader_thread = launch_measurements_thread(
-        cargo_test_pid.clone(),
-        child_pids.clone(),
-        db.clone(),
-        done.clone()
+    eprintln!(
+        "error: cargo-mlocktest only supports Linux, as it reads processes' \
+         locked memory from `/proc`"
     );
+    process::exit(EXIT_ERROR);
+}
 
-    // Get the system's locked memory limit.
-    let mlock_limit = run_prlimit();
+#[cfg(target_os = "linux")]
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = load_options(args).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        process::exit(EXIT_ERROR);
+    });
 
-    println!("\nMlock Monitor for `cargo test`");
-    println!("===============================");
-    println!("Locked memory limit (soft, kb): {}", mlock_limit.soft);
-    println!("Lock memory limit (hard, kb): {}", mlock_limit.hard);
-    print!("\nRunning `cargo test` ... ");
-
-    // Run `cargo test`.
-    let cwd = env::current_dir().unwrap();
-    let mut cargo_test_args = vec![
-        "test".to_string(),
-        format!("--manifest-path={}/Cargo.toml", cwd.to_str().unwrap()),
-    ];
-    cargo_test_args.extend(env::args().skip(1));
-    /*
-    let mut cargo_test_cmd = Command::new("cargo")
-        .args(&cargo_test_args)
-        .envs(env::vars())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    println!("Running `cargo test`: {:?}", cargo_test_cmd);
-    let cargo_test_output = cargo_test_cmd
-        .spawn()
-        .and_then(|child| {
-            *cargo_test_pid.lock().unwrap() = Some(child.id());
-            child.wait_with_output()
+    if options.check {
+        process::exit(if run_check() { EXIT_OK } else { EXIT_ERROR });
+    }
+    if options.verbose {
+        set_log_level(LogLevel::Verbose);
+    }
+    let report_on_stdout = options.format.is_machine_readable() && options.output_file.is_none();
+    REPORT_ON_STDOUT.store(report_on_stdout, Ordering::Relaxed);
+
+    // Without a `VmLck` field we would report 0 kb locked for every process,
+    // which looks like nothing was locked rather than like no data, so we
+    // check that our own "status" file reports it before running anything.
+    if parse_status_file(process::id()).is_none() {
+        eprintln!(
+            "error: `/proc/self/status` has no `VmLck` field, so locked memory \
+             can't be measured on this system (e.g. in some containers or \
+             kernel configurations)"
+        );
+        process::exit(EXIT_ERROR);
+    }
+
+    if let Some(pid) = options.watch_pid {
+        if !is_running(&options.config.procfs, pid) {
+            eprintln!("error: no process with pid {} is running", pid);
+            process::exit(EXIT_ERROR);
+        }
+    }
+
+    // Load the baseline report (and create the output file) up front so that
+    // we fail before running the tests rather than after.
+    let baseline = options.baseline.as_ref().map(|path| {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| Database::from_json(&json))
+            .unwrap_or_else(|e| {
+                eprintln!("error: failed to load the baseline `{}`: {}", path, e);
+                process::exit(EXIT_ERROR);
+            })
+    });
+    let create = |path: &String| {
+        File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: failed to create `{}`: {}", path, e);
+            process::exit(EXIT_ERROR);
         })
-        .unwrap();
-    */
-    let cargo_test_output = Command::new("cargo")
-        .args(&cargo_test_args)
-        .envs(env::vars())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .and_then(|child| {
-            *cargo_test_pid.lock().unwrap() = Some(child.id());
-            child.wait_with_output()
-        })  
-        .unwrap();
+    };
+    let output_file = options.output_file.as_ref().map(create);
+    let timeseries_file = options.timeseries_file.as_ref().map(create);
+
+    let stream: Option<Box<dyn Write + Send>> = match options.stream_jsonl.as_deref() {
+        None => None,
+        Some("-") => Some(Box::new(io::stdout())),
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Box::new(file)),
+            Err(e) => {
+                eprintln!("error: failed to open `{}`: {}", path, e);
+                process::exit(EXIT_ERROR);
+            }
+        },
+    };
+
+    // Get the system's locked memory limit. Unless running with
+    // `--require-limit`, a limit we can't get is treated as unlimited so that
+    // the locked memory is still measured.
+    let mlock_limit = run_prlimit().unwrap_or_else(|e| {
+        if options.require_limit {
+            eprintln!("error: failed to get the locked memory limits: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+        eprintln!(
+            "warning: failed to get the locked memory limits ({}); treating them as \
+             unlimited, so the limit check is skipped",
+            e,
+        );
+        MlockLimit { soft: Limit::Unlimited, hard: Limit::Unlimited }
+    });
+
+    // Warn as soon as a child process comes close to the soft limit.
+    // The limits only apply when measuring locked memory.
+    let measuring_locked = options.config.field == DEFAULT_FIELD;
+    let warn_kbs = match mlock_limit.soft {
+        Limit::Kb(kbs) if measuring_locked => {
+            Some(kbs.saturating_mul(options.warn_percent).div_ceil(100))
+        }
+        _ => None,
+    };
+
+    // Unlike `cargo test`, a user given command or watched process may lock
+    // memory itself, so we always measure it.
+    let include_root = options.include_parent
+        || options.command.is_some()
+        || options.watch_pid.is_some();
+
+    // Build the command to monitor, `cargo test` unless the user gave a
+    // command after `--exec` or a process to watch.
+    let monitored = (options.watch_pid, options.command);
+    let (command_name, program, program_args) = match monitored {
+        (Some(pid), _) => (format!("pid {}", pid), String::new(), vec![]),
+        (None, Some(mut command)) => {
+            let command_name = command.join(" ");
+            let program = command.remove(0);
+            (command_name, program, command)
+        }
+        (None, None) => {
+            // Default to the manifest in the current directory (or the
+            // nearest parent directory, like cargo), unless the user chose
+            // one, e.g. to test a crate in a workspace.
+            let mut manifest = None;
+            if !has_manifest_path(&options.cargo_test_args) {
+                let cwd = env::current_dir().unwrap();
+                manifest = Some(find_manifest(&cwd).unwrap_or_else(|| {
+                    eprintln!(
+                        "error: could not find `Cargo.toml` in `{}` or any parent \
+                         directory; run `cargo mlocktest` in a cargo project or pass \
+                         `--manifest-path`",
+                        cwd.display(),
+                    );
+                    process::exit(EXIT_ERROR);
+                }));
+            }
+            let cargo_test_args = cargo_test_command(options.cargo_test_args, manifest.as_deref());
+            // When run as `cargo mlocktest`, cargo sets `CARGO` to its own
+            // path, so we run the tests with the same cargo (and toolchain).
+            let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+            ("cargo test".to_string(), cargo, cargo_test_args)
+        }
+    };
+
+    let banner = format!("Mlock Monitor for `{}`", command_name);
+    sayln!("\n{}", banner);
+    sayln!("{}", "=".repeat(banner.chars().count()));
+    if options.raw_kb {
+        sayln!("Locked memory limit (soft, kb): {}", mlock_limit.soft);
+        sayln!("Lock memory limit (hard, kb): {}", mlock_limit.hard);
+    } else {
+        sayln!("Locked memory limit (soft): {:#}", mlock_limit.soft);
+        sayln!("Lock memory limit (hard): {:#}", mlock_limit.hard);
+    }
+    // A (nearly) zero soft limit makes any real `mlock` call fail, which
+    // otherwise shows up as mysterious test failures or an all-zero report.
+    // Processes with `CAP_IPC_LOCK` (which the tests inherit from us) aren't
+    // subject to the limit.
+    if let Limit::Kb(kbs) = mlock_limit.soft {
+        if kbs <= options.min_soft_limit_kb && has_cap_ipc_lock() != Some(true) {
+            eprintln!(
+                "\nwarning: the soft locked memory limit is only {} kb and we don't \
+                 have `CAP_IPC_LOCK`; tests that lock memory will likely fail (with \
+                 `EPERM` if the limit is 0, otherwise with `ENOMEM`), leaving nothing \
+                 locked to measure. Consider raising the limit with `ulimit -l <kbs>` \
+                 (or `ulimit -l unlimited`), or granting `CAP_IPC_LOCK`.",
+                kbs,
+            );
+        }
+    }
+
+    // The measurements of every run are merged into `db`, and the first
+    // nonzero exit code is kept.
+    let mut db = Database::new();
+    let mut cargo_test_code = Some(0);
+    let mut cargo_test_signal = None;
+    // With `--repeat-until-spike`, the run in which a process first locked
+    // more than `--max-locked-kb`, and the number of runs so far.
+    let mut spike_run = None;
+    let mut n_runs = 0;
+    // With `--stop-after`, the name of the process that we stopped after.
+    let mut stopped_after = None;
+    let child_env = child_env(env::vars(), options.clean_env, &options.env);
+    // The live view is redrawn in place, so it needs a terminal; otherwise we
+    // only print the report at the end. The command's output would be drawn
+    // over, so it is handled as with `--quiet`.
+    let tui = options.tui && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    if options.tui && !tui {
+        eprintln!("warning: `--tui` needs stdout to be a terminal; ignoring it");
+    }
+    let quiet = options.quiet || tui;
+    let live_metric = if measuring_locked { "Locked" } else { &options.config.field };
+    let mut stream = stream;
+    handle_signals();
+    let start = Instant::now();
+    // With `--self-profile`, the CPU time we have used before monitoring and
+    // the number of "status" files read while monitoring.
+    let cpu_start = read_cpu_time();
+    let mut n_reads = 0;
+    for run in 1..=options.runs {
+        // Initialize the values that will be shared between threads, and
+        // start the worker threads.
+        let shared = Shared::new();
+        let ps_thread = launch_ps_thread(
+            shared.clone(),
+            options.config.clone(),
+            include_root,
+            options.ignore.clone(),
+        );
+        let file_reader_thread = launch_measurements_thread(
+            shared.clone(),
+            options.config.clone(),
+            options.timeseries,
+            options.per_thread,
+            stream.take(),
+            warn_kbs,
+            None,
+        );
+
+        // Run the monitored command. Its output is streamed to our stdout and
+        // stderr as it runs, unless running with `--quiet`. When the report
+        // is on stdout, the command's stdout goes to our stderr.
+        let running = if options.watch_pid.is_some() { "Watching" } else { "Running" };
+        let of_runs = if options.runs > 1 {
+            format!(" (run {} of {})", run, options.runs)
+        } else {
+            String::new()
+        };
+        if quiet || options.watch_pid.is_some() {
+            say!("\n{} `{}`{} ... ", running, command_name, of_runs);
+        } else {
+            sayln!("\n{} `{}`{} ...\n", running, command_name, of_runs);
+        }
+        let _ = io::stdout().flush();
+        // When running with `--quiet`, the command's stderr is captured rather
+        // than discarded so that it can still be shown if the command fails,
+        // e.g. because the tests failed to compile.
+        let tui_thread = if tui {
+            let title = format!("{} `{}`{} ...", running, command_name, of_runs);
+            Some(launch_tui_thread(
+                shared.clone(),
+                title,
+                live_metric.to_string(),
+                options.raw_kb,
+                options.config.discovery_interval,
+            ))
+        } else {
+            None
+        };
+        let (stdout, stderr) = if quiet {
+            (Stdio::null(), Stdio::piped())
+        } else if report_on_stdout {
+            (Stdio::from(io::stderr()), Stdio::inherit())
+        } else {
+            (Stdio::inherit(), Stdio::inherit())
+        };
+        let mut captured_stderr = vec![];
+        let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+        // A watched process isn't our child, so we don't get its exit code; it
+        // is treated as having succeeded.
+        let status = match options.watch_pid {
+            Some(pid) => {
+                *shared.cargo_test_pid.lock().unwrap() = Some(pid);
+                wait_for_pid(&options.config.procfs, pid, deadline, &shared.stopped);
+                None
+            }
+            None => Some(Command::new(&program)
+                .args(&program_args)
+                .env_clear()
+                .envs(child_env.iter().map(|(key, val)| (key, val)))
+                .stdout(stdout)
+                .stderr(stderr)
+                .spawn()
+                .and_then(|mut child| {
+                    *shared.cargo_test_pid.lock().unwrap() = Some(child.id());
+                    // The pipe is drained on its own thread so that the
+                    // command can't block on writing to a full pipe while we
+                    // wait for it.
+                    let stderr_reader = child.stderr.take().map(|mut stderr| {
+                        thread::spawn(move || {
+                            let mut buf = vec![];
+                            let _ = stderr.read_to_end(&mut buf);
+                            buf
+                        })
+                    });
+                    let status = wait_for_child(&mut child, deadline, &shared.stopped)?;
+                    // If we were interrupted or timed out, the command's
+                    // descendants may still hold the pipe open, so we don't
+                    // wait for the reader.
+                    if let Some(stderr_reader) = stderr_reader {
+                        let stopped = interrupted()
+                            || TIMED_OUT.load(Ordering::SeqCst)
+                            || shared.stopped.load(Ordering::Acquire);
+                        if !stopped {
+                            captured_stderr = stderr_reader.join().unwrap_or_default();
+                        }
+                    }
+                    Ok(status)
+                })
+                .unwrap_or_else(|e| {
+                    eprintln!("\nerror: failed to run `{}`: {}", program, e);
+                    if e.kind() == io::ErrorKind::NotFound {
+                        eprintln!(
+                            "`{}` was not found; check that it is installed and on \
+                             your `PATH`",
+                            program,
+                        );
+                    }
+                    process::exit(EXIT_ERROR);
+                })),
+        };
+        // With `--stop-after`, we killed the monitored command ourselves, so
+        // its exit code is meaningless.
+        let stopped_early = shared.stopped.load(Ordering::Acquire);
+        let (code, signal) = match status {
+            Some(status) if !stopped_early => (status.code(), status.signal()),
+            _ => (Some(0), None),
+        };
+        if cargo_test_code == Some(0) {
+            cargo_test_code = code;
+            cargo_test_signal = signal;
+        }
+
+        // Once the monitored command has finished (or we were interrupted),
+        // stop the worker the threads and merge in the run's measurements.
+        // The measurements thread hands back the stream for the next run.
+        shared.done.store(true, Ordering::Release);
+        let _ = ps_thread.join();
+        if let Some(tui_thread) = tui_thread {
+            let _ = tui_thread.join();
+        }
+        stream = file_reader_thread.join().unwrap_or(None);
+        n_reads += shared.n_reads.load(Ordering::Relaxed);
+        let run_db = shared.into_db();
+        n_runs = run;
+        if options.repeat_until_spike {
+            let max_locked_kb = options.max_locked_kb.unwrap_or(0);
+            if !run_db.exceeding(max_locked_kb).is_empty() {
+                spike_run = Some(run);
+            }
+        }
+        db.merge_run(run_db);
+
+        let stopped =
+            interrupted() || TIMED_OUT.load(Ordering::SeqCst);
+        if stopped_early {
+            stopped_after = options.config.stop_after.clone();
+            sayln!("stopped!");
+        } else if !stopped {
+            sayln!("done!");
+        }
+        if code != Some(0) && !captured_stderr.is_empty() {
+            let heading = format!("`{}` failed, its stderr was:", command_name);
+            eprintln!("\n{}", heading);
+            eprintln!("{}\n", "=".repeat(heading.chars().count()));
+            let _ = io::stderr().write_all(&captured_stderr);
+        }
+        if stopped || stopped_early || spike_run.is_some() {
+            break;
+        }
+    }
+    let duration = start.elapsed();
+    let cpu_time = match (cpu_start, read_cpu_time()) {
+        (Some(cpu_start), Some(cpu_end)) => Some(cpu_end.saturating_sub(cpu_start)),
+        _ => None,
+    };
+    // The whole tree was monitored; only the report is filtered.
+    let n_observed = db.pinfos.len();
+    let mut census = db.census(n_observed);
+    if let Some(filter) = &options.filter {
+        let filtered = db.filtered(filter);
+        census = db.census(filtered.pinfos.len());
+        db = filtered;
+    }
+    // When Ctrl-C is hit, the monitored command may have received the SIGINT
+    // and exited on its own before we had a chance to kill it.
+    let interrupted_by = interrupted_by();
+    let timed_out = TIMED_OUT.load(Ordering::SeqCst);
+    if let Some(signal) = interrupted_by {
+        eprintln!(
+            "\ninterrupted ({}): printing the measurements collected so far",
+            signal_name(signal),
+        );
+    } else if timed_out {
+        eprintln!(
+            "\nTIMED OUT: `{}` exceeded the {}s timeout; printing the \
+             measurements collected so far",
+            command_name,
+            options.timeout.unwrap_or_default().as_secs(),
+        );
+    }
+    // Color is only used when writing to a terminal, and can be turned off
+    // using `--no-color` or by setting `NO_COLOR`.
+    let color = !options.no_color
+        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    let mut table_options = TableOptions {
+        sort: options.sort,
+        field: &options.config.field,
+        raw_kb: options.raw_kb,
+        stats: options.stats,
+        show_rss: options.show_rss,
+        show_samples: options.show_samples,
+        show_released: options.show_released,
+        hide_zero: options.hide_zero,
+        full_cmd: options.full_cmd,
+        name_width: options.name_width,
+        per_thread: options.per_thread,
+        top: options.top,
+        // Only the table written to the terminal is colored.
+        color: color && options.output_file.is_none(),
+        warn_percent: options.warn_percent,
+        group_by_name: options.group_by_name,
+        renderer: Renderer::Ascii,
+        style: TableStyle::default(),
+    };
+    let metric = if measuring_locked {
+        "locked memory".to_string()
+    } else {
+        options.config.field.clone()
+    };
+    let peak_total = if options.raw_kb {
+        format!("Peak total {} (kb): {}", metric, db.max_total_locked)
+    } else {
+        format!("Peak total {}: {}", metric, format_kbs(db.max_total_locked))
+    };
+    let mut report = match options.format {
+        Format::Table => {
+            let table = db.table(&mlock_limit.hard, &table_options);
+            format!("{}\n{}\n", table, peak_total)
+        }
+        Format::Markdown => {
+            table_options.renderer = Renderer::Markdown;
+            let table = db.table(&mlock_limit.hard, &table_options);
+            format!("\n{}\n\n{}\n", table, peak_total)
+        }
+        Format::Json => format!("\n{}\n", db.to_json()),
+        Format::JsonPretty => format!("\n{}\n", db.to_json_pretty()),
+        Format::Csv => format!("\n{}", db.to_csv()),
+    };
+    if options.timeseries && options.timeseries_file.is_none() {
+        report.push_str("\nLocked memory time series\n");
+        report.push_str("=========================\n");
+        report.push_str(&db.to_csv_timeseries());
+    }
+    match output_file {
+        Some(mut file) => {
+            let path = options.output_file.unwrap();
+            if let Err(e) = file.write_all(report.trim_start().as_bytes()) {
+                eprintln!("error: failed to write `{}`: {}", path, e);
+                process::exit(EXIT_ERROR);
+            }
+            sayln!("\nWrote the measurements report to `{}`", path);
+        }
+        // The leading blank line only separates the report from our other
+        // output.
+        None if report_on_stdout => print!("{}", report.trim_start()),
+        None => print!("{}", report),
+    };
+    if let Some(mut file) = timeseries_file {
+        let path = options.timeseries_file.as_ref().unwrap();
+        if let Err(e) = file.write_all(db.to_csv_timeseries().as_bytes()) {
+            eprintln!("error: failed to write `{}`: {}", path, e);
+            process::exit(EXIT_ERROR);
+        }
+        sayln!("Wrote the locked memory time series to `{}`", path);
+    }
+
+    // Check how close the child processes came to the `--against` locked
+    // memory limit. The limits apply to each process individually, so we
+    // compare them against the child process that locked the most memory.
+    let max_locked = db.max_locked();
+    let (name, limit) = match options.against {
+        Against::Soft => ("soft", &mlock_limit.soft),
+        Against::Hard => ("hard", &mlock_limit.hard),
+    };
+    // The limit check doesn't apply to other fields.
+    let verdict = if measuring_locked {
+        Verdict::check_with(max_locked, limit, options.warn_percent)
+    } else {
+        None
+    };
+    if measuring_locked {
+        sayln!("\nLocked memory limit check");
+        sayln!("=========================");
+        match verdict {
+            Some(verdict) => {
+                let verdict = if color {
+                    verdict.color().paint(&verdict.to_string())
+                } else {
+                    verdict.to_string()
+                };
+                if options.raw_kb {
+                    sayln!(
+                        "{}: max locked {} kb of {} kb {} limit",
+                        verdict, max_locked, limit, name,
+                    );
+                } else {
+                    sayln!(
+                        "{}: max locked {} of {:#} {} limit",
+                        verdict, format_kbs(max_locked), limit, name,
+                    );
+                }
+            }
+            None => sayln!("no {} limit applies (unlimited)", name),
+        };
+    }
+
+    // Check whether any child process locked more memory than allowed.
+    let mut exceeded_max_locked = false;
+    if let Some(max_locked_kb) = options.max_locked_kb {
+        if let Some(summary) = exceeded_summary(&db, max_locked_kb) {
+            exceeded_max_locked = true;
+            eprintln!("\n{}", summary);
+        }
+    }
+
+    // Compare each process' max locked memory against the baseline run.
+    let mut regressed = false;
+    if let Some(baseline) = &baseline {
+        let threshold = options.regression_threshold_kb;
+        let deltas = db.compare(baseline);
+        sayln!("\nComparison with baseline `{}`", options.baseline.as_ref().unwrap());
+        sayln!("{}", baseline_table(&deltas, threshold).render(Renderer::Ascii));
+        let regressions: Vec<&Delta> =
+            deltas.iter().filter(|delta| delta.increase() > threshold).collect();
+        if !regressions.is_empty() {
+            regressed = true;
+            sayln!("\nRegressed by more than {} kb:", threshold);
+            for delta in regressions {
+                sayln!("{}: +{} kb", delta.pname, delta.increase());
+            }
+        }
+    }
+
+    // An empty database means that we never observed a child process, e.g.
+    // because they all exited before being found by `run_ps`.
+    if n_observed == 0 {
+        eprintln!(
+            "\nwarning: no child processes were observed; the measurements \
+             report is empty"
+        );
+    } else if db.is_empty() {
+        let noun = if n_observed == 1 { "process" } else { "processes" };
+        eprintln!(
+            "\nwarning: `--filter` matched none of the {} observed {}; the \
+             measurements report is empty",
+            n_observed, noun,
+        );
+    }
+    let missing_samples = options.require_samples && n_observed == 0;
+
+    sayln!("\nRun summary");
+    sayln!("===========");
+    let ended = match &stopped_after {
+        Some(name) => format!("was stopped once a `{}` process exited", name),
+        None => {
+            let killed_for = match interrupted_by {
+                Some(signal) => Some(format!("interrupted by {}", signal_name(signal))),
+                None if timed_out => Some("`--timeout-secs` elapsed".to_string()),
+                None => None,
+            };
+            let watched = options.watch_pid.is_some();
+            describe_exit(watched, cargo_test_code, cargo_test_signal, killed_for.as_deref())
+        }
+    };
+    sayln!("`{}` {} after {:.2}s", command_name, ended, duration.as_secs_f64());
+    sayln!("{}", census);
+    if options.self_profile {
+        sayln!("{}", overhead_summary(cpu_time, duration, n_reads));
+        let interval = options.config.measurement_interval;
+        sayln!("{}", resolution_summary(db.sweep_resolution(), interval));
+    }
+    if options.repeat_until_spike {
+        match spike_run {
+            Some(run) => sayln!("Spiked in run {} of at most {}", run, options.runs),
+            None => sayln!("No spike in {} runs", n_runs),
+        }
+    }
 
-    // Once `cargo test` has finished, stop the worker the threads and
-    // print the measurement results.
-    println!("done!");
-    done.store(true, Ordering::Relaxed);
-    let _ = ps_thread.join();
-    let _ = file_reader_thread.join();
-    println!("{}", db.lock().unwrap().table());
+    let failed_check = if missing_samples {
+        Some(EXIT_NO_SAMPLES)
+    } else if exceeded_max_locked || regressed {
+        Some(EXIT_LIMIT_EXCEEDED)
+    } else {
+        None
+    };
+    let code = exit_code(cargo_test_code, interrupted_by, timed_out, failed_check);
+    if options.syslog {
+        let summary = RunSummary { limit_name: name, limit, verdict, exit_code: code };
+        write_syslog(&syslog_records(&db, &summary));
+    }
+    process::exit(code);
+}
 
-    println!("\nOutput `cargo test`");
-    println!("====================");
-    println!("{}", String::from_utf8_lossy(&cargo_test_output.stdout));
+// Describes the monitor's own overhead: the CPU time it used (if it could be
+// read) during the `wall` time spent monitoring, and the number of "status"
+// files it read.
+fn overhead_summary(cpu_time: Option<Duration>, wall: Duration, n_reads: u64) -> String {
+    let secs = wall.as_secs_f64();
+    let per_sec = if secs > 0.0 { n_reads as f64 / secs } else { 0.0 };
+    let reads = format!("{} status reads ({:.0}/s)", n_reads, per_sec);
+    match cpu_time {
+        Some(cpu_time) if secs > 0.0 => format!(
+            "Monitor overhead: {:.2}s of CPU time ({:.1}% of {:.2}s), {}",
+            cpu_time.as_secs_f64(),
+            cpu_time.as_secs_f64() / secs * 100.0,
+            secs,
+            reads,
+        ),
+        _ => format!("Monitor overhead: unknown CPU time, {}", reads),
+    }
 }
 
+// Describes the measurement resolution that was achieved: the median and max
+// time between measurement sweeps, compared to the `requested` interval. The
+// locks and reads of each sweep make it longer than the interval we sleep.
+fn resolution_summary(
+    resolution: Option<(Duration, Duration)>,
+    requested: Duration,
+) -> String {
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    match resolution {
+        Some((median, max)) => format!(
+            "Measurement interval: median {:.1}ms, max {:.1}ms (requested {:.1}ms)",
+            ms(median),
+            ms(max),
+            ms(requested),
+        ),
+        None => "Measurement interval: unknown (fewer than two measurements)".to_string(),
+    }
+}
+
+// The outcome of a run, as written to syslog.
+struct RunSummary<'a> {
+    // The `--against` limit and the limit check's verdict, if it applies.
+    limit_name: &'a str,
+    limit: &'a Limit,
+    verdict: Option<Verdict>,
+    exit_code: i32,
+}
+
+// Builds the syslog records summarizing a run: one for the run, followed by
+// one for each child process. Each record is a list of `key=value` fields so
+// that it can be searched for, e.g. with `journalctl -t cargo-mlocktest`.
+fn syslog_records(db: &Database, summary: &RunSummary) -> Vec<String> {
+    let verdict = summary.verdict.map_or("n/a".to_string(), |verdict| verdict.to_string());
+    let result = if summary.exit_code == 0 { "pass" } else { "fail" };
+    let mut records = vec![format!(
+        "run result={} exit_code={} peak_total_kb={} max_locked_kb={} {}_limit_kb={} \
+         limit_check={}",
+        result,
+        summary.exit_code,
+        db.max_total_locked,
+        db.max_locked(),
+        summary.limit_name,
+        summary.limit,
+        verdict,
+    )];
+    for (key, pinfo) in db.sorted(Sort::Pid) {
+        records.push(format!(
+            "process pid={} pname={:?} max_locked_kb={}",
+            key.pid, pinfo.pname, pinfo.max_locked,
+        ));
+    }
+    records
+}
+
+// Writes each record to syslog, which journald also collects.
+#[cfg(target_os = "linux")]
+fn write_syslog(records: &[String]) {
+    let ident = b"cargo-mlocktest\0".as_ptr() as *const libc::c_char;
+    let format = b"%s\0".as_ptr() as *const libc::c_char;
+    unsafe {
+        libc::openlog(ident, libc::LOG_PID, libc::LOG_USER);
+    }
+    for record in records {
+        // A record can't contain a NUL, as it's passed as a C string.
+        let record = match CString::new(record.as_str()) {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        unsafe {
+            libc::syslog(libc::LOG_INFO, format, record.as_ptr());
+        }
+    }
+    unsafe {
+        libc::closelog();
+    }
+}
+
+// Returns `cargo mlocktest`'s exit code: `cargo test`'s exit code, so that
+// failing tests fail the `cargo mlocktest` run. If `cargo test` was
+// terminated by a signal it has no exit code, in which case we exit with
+// `EXIT_KILLED`. If the tests passed but one of our checks failed, we exit
+// with `failed_check`'s exit code. If we were interrupted by a signal, we exit
+// with `EXIT_SIGNAL_BASE` plus the signal's number, and if we timed out, with
+// `EXIT_TIMED_OUT`.
+fn exit_code(
+    cargo_test_code: Option<i32>,
+    interrupted_by: Option<libc::c_int>,
+    timed_out: bool,
+    failed_check: Option<i32>,
+) -> i32 {
+    if let Some(signal) = interrupted_by {
+        return EXIT_SIGNAL_BASE + signal;
+    }
+    match cargo_test_code {
+        _ if timed_out => EXIT_TIMED_OUT,
+        Some(0) => failed_check.unwrap_or(EXIT_OK),
+        Some(code) => code,
+        None => EXIT_KILLED,
+    }
+}
+
+// Lists the child processes that locked more than `max_locked_kb`, or returns
+// `None` if none did. This is printed to stderr, so that it is seen even when
+// the report is written to a file or stdout is piped elsewhere.
+fn exceeded_summary(db: &Database, max_locked_kb: u64) -> Option<String> {
+    let exceeding = db.exceeding(max_locked_kb);
+    if exceeding.is_empty() {
+        return None;
+    }
+    let mut summary = format!("Exceeded max locked memory ({} kb):", max_locked_kb);
+    for (key, Pinfo { pname, max_locked, .. }) in exceeding {
+        summary.push_str(&format!("\n{} (pid {}): {} kb", pname, key.pid, max_locked));
+    }
+    Some(summary)
+}
+
+
 #[cfg(test)]
 mod tests {
+    use std::env;
+    use std::fs;
     use std::mem::size_of_val;
+    use std::path::Path;
+    use std::process;
     use std::thread;
     use std::time::Duration;
 
-    use memsec::mlock;
+    use memsec::{mlock, munlock};
+
+    use cargo_mlocktest::{parse_status_file, Config, Database, Limit, ProcKey, Status, Verdict};
+
+    use super::{
+        apply_config, cargo_test_command, child_env, describe_exit, exceeded_summary, exit_code,
+        find_manifest, has_manifest_path, live_table, load_options, overhead_summary,
+        resolution_summary, syslog_records, Against, Format, Options, Parser, RunSummary,
+        DEFAULT_MAX_ITERS, EXIT_LIMIT_EXCEEDED, EXIT_NO_SAMPLES,
+    };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn parse_args(args: Vec<String>) -> Result<Options, String> {
+        let mut parser = Parser::new();
+        parser.parse_args(args)?;
+        parser.finish()
+    }
+
+    fn parse_config(contents: &str) -> Result<Options, String> {
+        let mut parser = Parser::new();
+        apply_config(contents, &mut parser)?;
+        parser.finish()
+    }
 
     #[test]
     fn test_mlock() {
-        println!("TEST TEST TEST");
         let buf: [u64; 600] = [555; 600];
-        let ptr = (&buf).as_ptr() as *mut u8;
-        unsafe {
-            mlock(ptr, size_of_val(&buf));
-        }
+        let ptr = buf.as_ptr() as *mut u8;
+        assert!(unsafe { mlock(ptr, size_of_val(&buf)) });
+        // The locked pages are counted in our "status" file, which is what
+        // `cargo mlocktest` measures when it is run on its own tests.
+        assert!(parse_status_file(process::id()).unwrap().vmlck > 0);
         thread::sleep(Duration::from_secs(2));
-        assert!(true);
+        assert!(unsafe { munlock(ptr, size_of_val(&buf)) });
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(&["--format=json", "--release"])).unwrap();
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(options.cargo_test_args, args(&["--release"]));
+
+        let options = parse_args(args(&["--format=json-pretty"])).unwrap();
+        assert_eq!(options.format, Format::JsonPretty);
+
+        let options = parse_args(args(&["--exec", "./bin", "--format=json"])).unwrap();
+        assert_eq!(options.format, Format::Table);
+        assert_eq!(options.command, Some(args(&["./bin", "--format=json"])));
+        let options = parse_args(args(&["--release", "--", "--nocapture", "--quiet"])).unwrap();
+        assert!(options.command.is_none() && !options.quiet);
+        assert_eq!(options.cargo_test_args, args(&["--release", "--", "--nocapture", "--quiet"]));
+
+        assert!(parse_args(args(&["--format=xml"])).is_err());
+
+        let options = parse_args(args(&["--ignore=sh,make", "--ignore-add=cc"])).unwrap();
+        assert_eq!(options.ignore, args(&["sh", "make", "cc"]));
+        assert!(options.cargo_test_args.is_empty());
+        let options = parse_args(args(&["--ignore="])).unwrap();
+        assert!(options.ignore.is_empty());
+        assert!(parse_args(args(&["--no-ignore"])).unwrap().ignore.is_empty());
+        assert!(parse_args(args(&["--no-ignore", "--ignore-add=cc"])).is_err());
+        assert!(parse_args(args(&["--exec"])).is_err());
+        assert!(parse_args(args(&["--release", "--exec", "./bin"])).is_err());
+
+        let options = parse_args(args(&[])).unwrap();
+        assert!(!options.check);
+        assert_eq!(options.warn_percent, 90);
+        let options = parse_args(args(&["--warn-percent=75"])).unwrap();
+        assert_eq!(options.warn_percent, 75);
+        assert!(parse_args(args(&["--warn-percent=0"])).is_err());
+        assert!(parse_args(args(&["--warn-percent=101"])).is_err());
+        assert_eq!(options.min_soft_limit_kb, 64);
+        let options = parse_args(args(&["--min-soft-limit-kb=0"])).unwrap();
+        assert_eq!(options.min_soft_limit_kb, 0);
+
+        let options = parse_args(args(&["--output-file=report.txt"])).unwrap();
+        assert_eq!(options.output_file, Some("report.txt".to_string()));
+        assert!(options.cargo_test_args.is_empty());
+
+        let options = parse_args(args(&["--fast-poll-ms=0"])).unwrap();
+        assert_eq!(options.config.fast_discovery_window, Duration::from_millis(0));
+        let options = parse_args(args(&["--interval-ms=10", "--measure-ms=2"])).unwrap();
+        assert_eq!(options.config.discovery_interval, Duration::from_millis(10));
+        assert_eq!(options.config.measurement_interval, Duration::from_millis(2));
+        let options = parse_args(args(&["--discovery-ms=50"])).unwrap();
+        let expected = Duration::from_millis(50);
+        let config = Config { discovery_interval: expected, ..Config::default() };
+        assert_eq!(options.config, config);
+        assert!(parse_args(args(&["--measure-ms=fast"])).is_err());
+
+        let options = parse_args(args(&["--stream-jsonl"])).unwrap();
+        assert_eq!(options.stream_jsonl, Some("-".to_string()));
+        assert!(parse_args(args(&["--stream-jsonl", "--format=csv"])).is_err());
+        assert!(parse_args(args(&["--timeseries", "--format=json"])).is_err());
+        let options = parse_args(args(&["--timeseries=ts.csv", "--format=json"])).unwrap();
+        assert!(options.timeseries);
+        assert_eq!(options.timeseries_file.as_deref(), Some("ts.csv"));
+        let options = parse_args(args(&["--stream-jsonl=samples.jsonl"])).unwrap();
+        assert_eq!(options.stream_jsonl, Some("samples.jsonl".to_string()));
+
+        let options = parse_args(args(&["--include-parent", "--release"])).unwrap();
+        assert!(options.include_parent);
+        assert_eq!(options.cargo_test_args, args(&["--release"]));
+
+        let options = parse_args(args(&["--timeout-secs=60"])).unwrap();
+        assert_eq!(options.timeout, Some(Duration::from_secs(60)));
+        assert!(parse_args(args(&["--timeout-secs=1m"])).is_err());
+
+        let options = parse_args(args(&["--watch-pid=42"])).unwrap();
+        assert_eq!(options.watch_pid, Some(42));
+        assert!(parse_args(args(&["--watch-pid=42", "--exec", "./bin"])).is_err());
+        assert!(parse_args(args(&["--watch-pid=42", "--release"])).is_err());
+        assert_eq!(parse_args(args(&["--runs=3"])).unwrap().runs, 3);
+        assert!(parse_args(args(&["--runs=0"])).is_err());
+        assert_eq!(parse_args(args(&["--field=VmSwap"])).unwrap().config.field, "VmSwap");
+        assert!(parse_args(args(&["--field=VmLck:"])).is_err());
+        let options = parse_args(args(&["--procfs=/host/proc/"])).unwrap();
+        assert_eq!(options.config.procfs, "/host/proc");
+        assert_eq!(parse_args(args(&[])).unwrap().config.procfs, "/proc");
+        assert!(parse_args(args(&["--proc-events"])).unwrap().config.proc_events);
+        let options = parse_args(args(&["--stop-after=my_test"])).unwrap();
+        assert_eq!(options.config.stop_after.as_deref(), Some("my_test"));
+        assert!(parse_args(args(&["--stop-after="])).is_err());
+        assert!(parse_args(args(&["--proc-events", "--procfs=/host/proc"])).is_err());
+        assert!(parse_args(args(&["--require-limit"])).unwrap().require_limit);
+        assert!(!parse_args(args(&[])).unwrap().require_limit);
+        assert!(parse_args(args(&["--watch-pid=42", "--runs=2"])).is_err());
+        assert_eq!(parse_args(args(&[])).unwrap().against, Against::Soft);
+        assert_eq!(parse_args(args(&["--against=hard"])).unwrap().against, Against::Hard);
+        assert!(parse_args(args(&["--against=both"])).is_err());
+        assert_eq!(parse_args(args(&["--name-width=20"])).unwrap().name_width, Some(20));
+        assert!(parse_args(args(&["--name-width=0"])).is_err());
+
+        let options =
+            parse_args(args(&["--repeat-until-spike", "--max-locked-kb=64"])).unwrap();
+        assert!(options.repeat_until_spike);
+        assert_eq!(options.runs, DEFAULT_MAX_ITERS);
+        let options = parse_args(args(&[
+            "--repeat-until-spike",
+            "--max-iters=5",
+            "--max-locked-kb=64",
+        ]))
+        .unwrap();
+        assert_eq!(options.runs, 5);
+        assert!(parse_args(args(&["--repeat-until-spike"])).is_err());
+        assert!(parse_args(args(&["--max-iters=5", "--max-locked-kb=64"])).is_err());
+        let spike_and_runs = ["--repeat-until-spike", "--max-locked-kb=64", "--runs=2"];
+        assert!(parse_args(args(&spike_and_runs)).is_err());
+    }
+
+    #[test]
+    fn test_apply_config() {
+        let contents = concat!(
+            "# Our CI's defaults.\n",
+            "format = \"json\"\n",
+            "interval_ms = 5 # as often as we can afford\n",
+            "max-locked-kb = 1024\n",
+            "ignore = [\"sh\", 'make']\n",
+            "quiet = true\n",
+            "stats = false\n",
+            "filter = \"#1\"\n",
+            "\n",
+            "[env]\n",
+            "RUST_LOG = \"debug\"\n",
+        );
+        let options = parse_config(contents).unwrap();
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(options.config.discovery_interval, Duration::from_millis(5));
+        assert_eq!(options.config.measurement_interval, Duration::from_millis(5));
+        assert_eq!(options.max_locked_kb, Some(1024));
+        assert_eq!(options.ignore, args(&["sh", "make"]));
+        assert!(options.quiet && !options.stats);
+        assert_eq!(options.filter.as_deref(), Some("#1"));
+        assert_eq!(options.env, [("RUST_LOG".to_string(), "debug".to_string())]);
+        assert!(options.cargo_test_args.is_empty());
+        assert!(parse_config("quiet\n").unwrap_err().starts_with("line 1:"));
+        assert!(parse_config("format = json\n").is_err());
+        assert!(parse_config("format = \"xml\"\n").unwrap_err().starts_with("line 1:"));
+        assert!(parse_config("format = true\n").is_err());
+        assert!(parse_config("quiet = 1\n").is_err());
+        assert!(parse_config("release = true\n").is_err());
+        assert!(parse_config("[table]\n").is_err());
+        assert!(parse_config("config = \"other.toml\"\n").is_err());
+        assert!(parse_config("[env]\nRUST_LOG = true\n").is_err());
+    }
+
+    #[test]
+    fn test_load_config() {
+        let path = env::temp_dir().join(format!("mlocktest-{}.toml", process::id()));
+        let config = format!("--config={}", path.display());
+        fs::write(&path, "top = 3\nquiet = true\n").unwrap();
+        let options = load_options(args(&[&config])).unwrap();
+        assert_eq!(options.top, Some(3));
+        // The command line overrides the config file.
+        let options = load_options(args(&["mlocktest", "--top=5", &config])).unwrap();
+        assert_eq!(options.top, Some(5));
+        assert!(options.quiet && options.cargo_test_args.is_empty());
+        fs::write(&path, "no_such_option = 1\n").unwrap();
+        assert!(load_options(args(&[&config])).unwrap_err().contains("unknown option"));
+        fs::remove_file(&path).unwrap();
+        assert!(load_options(args(&[&config])).is_err());
+    }
+
+    #[test]
+    fn test_child_env() {
+        let vars = [("PATH", "/bin"), ("AWS_SECRET", "x"), ("RUSTUP_TOOLCHAIN", "1.0")];
+        let vars = || vars.iter().map(|(key, val)| (key.to_string(), val.to_string()));
+        let extra = vec![("RUST_LOG".to_string(), "debug".to_string())];
+        let keys = |env: Vec<(String, String)>| -> Vec<String> {
+            env.into_iter().map(|(key, _)| key).collect()
+        };
+        let all = ["PATH", "AWS_SECRET", "RUSTUP_TOOLCHAIN", "RUST_LOG"];
+        assert_eq!(keys(child_env(vars(), false, &extra)), all);
+        let clean = ["PATH", "RUSTUP_TOOLCHAIN", "RUST_LOG"];
+        assert_eq!(keys(child_env(vars(), true, &extra)), clean);
+
+        let options = parse_args(args(&["--clean-env", "--env=A=b=c"])).unwrap();
+        assert!(options.clean_env);
+        assert_eq!(options.env, vec![("A".to_string(), "b=c".to_string())]);
+        assert!(parse_args(args(&["--env=A"])).is_err());
+        assert!(parse_args(args(&["--env==b"])).is_err());
+        let options = parse_args(args(&["--env", "A=1", "--env=B=2", "--release"])).unwrap();
+        assert_eq!(options.env, vec![
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "2".to_string()),
+        ]);
+        assert_eq!(options.cargo_test_args, args(&["--release"]));
+        assert!(parse_args(args(&["--env"])).is_err());
+    }
+
+    #[test]
+    fn test_live_table() {
+        let mut db = Database::new();
+        for (pid, pname) in &[(1, "a"), (2, "b")] {
+            let key = ProcKey { pid: *pid, starttime: 0 };
+            db.new_child_process(key, pname.to_string(), pname.to_string());
+        }
+        let status = |vmlck| Status { vmlck, vmrss: 0, vmhwm: 0 };
+        db.update(ProcKey { pid: 1, starttime: 0 }, &status(64));
+        db.update(ProcKey { pid: 1, starttime: 0 }, &status(0));
+        db.update(ProcKey { pid: 2, starttime: 0 }, &status(8));
+        let table = live_table(&db, "Locked", true);
+        assert_eq!(table.headings[1], "Current Locked (kb)");
+        assert_eq!(table.rows, vec![vec!["b", "8", "8"], vec!["a", "0", "64"]]);
+    }
+
+    #[test]
+    fn test_overhead_summary() {
+        let cpu_time = Some(Duration::from_millis(250));
+        let expected = "Monitor overhead: 0.25s of CPU time (5.0% of 5.00s), 5000 status \
+                        reads (1000/s)";
+        assert_eq!(overhead_summary(cpu_time, Duration::from_secs(5), 5000), expected);
+        let expected = "Monitor overhead: unknown CPU time, 0 status reads (0/s)";
+        assert_eq!(overhead_summary(None, Duration::from_secs(0), 0), expected);
+        let resolution = Some((Duration::from_micros(1250), Duration::from_millis(15)));
+        let expected = "Measurement interval: median 1.2ms, max 15.0ms (requested 1.0ms)";
+        assert_eq!(resolution_summary(resolution, Duration::from_millis(1)), expected);
+    }
+
+    #[test]
+    fn test_syslog_records() {
+        let mut db = Database::new();
+        for (pid, pname, vmlck) in &[(2, "my test", 64), (1, "a", 8)] {
+            let key = ProcKey { pid: *pid, starttime: 0 };
+            db.new_child_process(key, pname.to_string(), pname.to_string());
+            db.update(key, &Status { vmlck: *vmlck, vmrss: 0, vmhwm: 0 });
+        }
+        db.update_total(72);
+        let limit = Limit::Kb(64);
+        let summary = RunSummary {
+            limit_name: "soft",
+            limit: &limit,
+            verdict: Some(Verdict::Fail),
+            exit_code: 1,
+        };
+        let expected = vec![
+            "run result=fail exit_code=1 peak_total_kb=72 max_locked_kb=64 soft_limit_kb=64 \
+             limit_check=FAIL",
+            "process pid=1 pname=\"a\" max_locked_kb=8",
+            "process pid=2 pname=\"my test\" max_locked_kb=64",
+        ];
+        assert_eq!(syslog_records(&db, &summary), expected);
+    }
+
+    #[test]
+    fn test_parse_args_subcommand() {
+        // `cargo mlocktest --raw-kb my_test` runs
+        // `cargo-mlocktest mlocktest --raw-kb my_test`.
+        let options = parse_args(args(&["mlocktest", "--raw-kb", "my_test"])).unwrap();
+        assert!(options.raw_kb);
+        assert_eq!(options.cargo_test_args, args(&["my_test"]));
+        let options = parse_args(args(&["my_test", "mlocktest"])).unwrap();
+        assert_eq!(options.cargo_test_args, args(&["my_test", "mlocktest"]));
+        // Only the subcommand's name is skipped, so this runs the tests
+        // matching `mlocktest`.
+        let options = load_options(args(&["mlocktest", "mlocktest"])).unwrap();
+        assert_eq!(options.cargo_test_args, args(&["mlocktest"]));
+    }
+
+    #[test]
+    fn test_has_manifest_path() {
+        assert!(!has_manifest_path(&args(&["--release"])));
+        assert!(has_manifest_path(&args(&["--manifest-path=a/Cargo.toml"])));
+        assert!(has_manifest_path(&args(&["--manifest-path", "a/Cargo.toml"])));
+        assert!(!has_manifest_path(&args(&["--", "--manifest-path"])));
+    }
+
+    #[test]
+    fn test_cargo_test_command() {
+        // Unlike `--exec`, a `--` and the arguments after it reach the test
+        // binaries through `cargo test`.
+        let options = parse_args(args(&["--release", "--", "--nocapture", "--exec"])).unwrap();
+        assert!(options.command.is_none());
+        let manifest = Path::new("a/Cargo.toml");
+        let expected = args(&[
+            "test",
+            "--manifest-path=a/Cargo.toml",
+            "--release",
+            "--",
+            "--nocapture",
+            "--exec",
+        ]);
+        assert_eq!(cargo_test_command(options.cargo_test_args, Some(manifest)), expected);
+        assert_eq!(cargo_test_command(args(&["--", "-q"]), None), args(&["test", "--", "-q"]));
+    }
+
+    #[test]
+    fn test_json_report_with_max_locked() {
+        let options = parse_args(args(&[
+            "--format=json",
+            "--output-file=report.json",
+            "--max-locked-kb=32",
+        ]))
+        .unwrap();
+        assert_eq!(options.format, Format::Json);
+        assert_eq!(options.output_file.as_deref(), Some("report.json"));
+        assert_eq!(options.max_locked_kb, Some(32));
+
+        let mut db = Database::new();
+        for (pid, pname, vmlck) in &[(1, "a", 64), (2, "b", 8)] {
+            let key = ProcKey { pid: *pid, starttime: 0 };
+            db.new_child_process(key, pname.to_string(), pname.to_string());
+            db.update(key, &Status { vmlck: *vmlck, vmrss: 0, vmhwm: 0 });
+        }
+        // The report still includes every process, while the summary only
+        // lists those that exceeded the max locked memory.
+        assert!(db.to_json().contains("\"pname\":\"b\""));
+        let expected = "Exceeded max locked memory (32 kb):\na (pid 1): 64 kb";
+        assert_eq!(exceeded_summary(&db, 32).as_deref(), Some(expected));
+        assert_eq!(exceeded_summary(&db, 64), None);
+
+        // Exceeding the max locked memory fails a run whose tests passed.
+        let exceeded = Some(EXIT_LIMIT_EXCEEDED);
+        assert_eq!(exit_code(Some(0), None, false, exceeded), 2);
+        assert_eq!(exit_code(Some(0), None, false, Some(EXIT_NO_SAMPLES)), 4);
+        assert_eq!(exit_code(Some(0), None, false, None), 0);
+        assert_eq!(exit_code(Some(101), None, false, exceeded), 101);
+        assert_eq!(exit_code(None, None, false, None), 1);
+        assert_eq!(exit_code(Some(0), Some(libc::SIGINT), false, exceeded), 130);
+        assert_eq!(exit_code(Some(0), Some(libc::SIGTERM), false, exceeded), 143);
+        // `cargo test` is killed along with us, which isn't `EXIT_KILLED`.
+        assert_eq!(exit_code(None, Some(libc::SIGINT), false, None), 130);
+        assert_eq!(exit_code(Some(0), None, true, exceeded), 124);
+    }
+
+    #[test]
+    fn test_find_manifest() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let manifest = Some(root.join("Cargo.toml"));
+        assert_eq!(find_manifest(root), manifest);
+        assert_eq!(find_manifest(&root.join("src")), manifest);
+    }
+
+    #[test]
+    fn test_describe_exit() {
+        assert_eq!(describe_exit(false, Some(101), None, None), "exited with code 101");
+        assert_eq!(
+            describe_exit(false, Some(137), None, None),
+            "exited with code 137, the code for being terminated by SIGKILL",
+        );
+        assert_eq!(
+            describe_exit(false, None, Some(libc::SIGTERM), None),
+            "was terminated by SIGTERM",
+        );
+        assert_eq!(
+            describe_exit(false, None, Some(libc::SIGKILL), None),
+            "was terminated by SIGKILL",
+        );
+        assert_eq!(
+            describe_exit(false, None, Some(libc::SIGKILL), Some("`--timeout-secs` elapsed")),
+            "was killed by `cargo mlocktest` with SIGKILL (`--timeout-secs` elapsed)",
+        );
+        assert_eq!(
+            describe_exit(false, None, Some(libc::SIGSEGV), Some("`--timeout-secs` elapsed")),
+            "was terminated by SIGSEGV",
+        );
+        assert_eq!(describe_exit(false, None, None, None), "was terminated by a signal");
+        assert_eq!(describe_exit(true, Some(0), None, None), "exited");
     }
 }